@@ -0,0 +1,445 @@
+// check-primers 子命令：订购引物前的计算机质控。使用与分析相同的 load_primers 加载引物文件，
+// 检查 GC 含量、最近邻 Tm、同聚物、自身互补（3' 端自身二聚体和发夹）、引物间 3' 端互补，
+// 以及在最大错配数内无法区分的引物
+use crate::{align_sequence, load_primers, Primer, PrimerFormat, PrimerOptions};
+use anyhow::{Context, Result};
+use clap::Args;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct CheckPrimersArgs {
+    /// 引物序列文件(TSV格式：name\tsequence[\tmax_errors])，也支持 FASTA、CSV 和 BED 引物方案
+    #[arg(short, long)]
+    primers: String,
+
+    /// 引物文件格式，auto 按扩展名和内容自动判断
+    #[arg(long, value_enum, default_value = "auto")]
+    primer_format: PrimerFormat,
+
+    /// 参考基因组 FASTA 文件，BED 引物方案没有序列列时用于提取引物序列
+    #[arg(long)]
+    reference: Option<String>,
+
+    /// 输出目录
+    #[arg(short = 'O', long, default_value = "output")]
+    outdir: String,
+
+    /// 引物文件中存在重复或冲突的引物时只给出警告而不报错
+    #[arg(long)]
+    allow_primer_collisions: bool,
+
+    /// 最大允许错配数，用于判断引物是否可区分
+    #[arg(short = 'e', long, default_value = "3")]
+    max_errors: i32,
+
+    /// 按引物长度计算最大错配数，设置后代替 --max-errors
+    #[arg(long)]
+    max_error_rate: Option<f64>,
+
+    /// GC 含量下限（%）
+    #[arg(long, default_value = "40")]
+    min_gc: f64,
+
+    /// GC 含量上限（%）
+    #[arg(long, default_value = "60")]
+    max_gc: f64,
+
+    /// Tm 下限（°C）
+    #[arg(long, default_value = "50")]
+    min_tm: f64,
+
+    /// Tm 上限（°C）
+    #[arg(long, default_value = "65")]
+    max_tm: f64,
+
+    /// 允许的最长同聚物
+    #[arg(long, default_value = "4")]
+    max_homopolymer: usize,
+
+    /// 允许的最长发夹茎长度
+    #[arg(long, default_value = "4")]
+    max_hairpin: usize,
+
+    /// 允许的最长 3' 端互补长度（自身二聚体和引物间二聚体）
+    #[arg(long, default_value = "4")]
+    max_dimer: usize,
+
+    /// 导致非零退出码的检查项，逗号分隔
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "gc,tm,homopolymer,hairpin,self-dimer,cross-dimer,indistinguishable"
+    )]
+    fail_on: Vec<Check>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Check {
+    Gc,
+    Tm,
+    Homopolymer,
+    Hairpin,
+    SelfDimer,
+    CrossDimer,
+    Indistinguishable,
+}
+
+impl Check {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Check::Gc => "gc",
+            Check::Tm => "tm",
+            Check::Homopolymer => "homopolymer",
+            Check::Hairpin => "hairpin",
+            Check::SelfDimer => "self-dimer",
+            Check::CrossDimer => "cross-dimer",
+            Check::Indistinguishable => "indistinguishable",
+        }
+    }
+}
+
+// 最近邻参数（SantaLucia 1998）：二核苷酸 -> (ΔH kcal/mol, ΔS cal/K·mol)
+fn nearest_neighbor(pair: &[u8]) -> Option<(f64, f64)> {
+    match pair {
+        b"AA" | b"TT" => Some((-7.9, -22.2)),
+        b"AT" => Some((-7.2, -20.4)),
+        b"TA" => Some((-7.2, -21.3)),
+        b"CA" | b"TG" => Some((-8.5, -22.7)),
+        b"GT" | b"AC" => Some((-8.4, -22.4)),
+        b"CT" | b"AG" => Some((-7.8, -21.0)),
+        b"GA" | b"TC" => Some((-8.2, -22.2)),
+        b"CG" => Some((-10.6, -27.2)),
+        b"GC" => Some((-9.8, -24.4)),
+        b"GG" | b"CC" => Some((-8.0, -19.9)),
+        _ => None,
+    }
+}
+
+// 计算 Tm 使用的 Na+ 浓度（mol/L）和引物浓度（mol/L）
+const NA_CONCENTRATION: f64 = 0.05;
+const PRIMER_CONCENTRATION: f64 = 250e-9;
+
+// 最近邻法 Tm（°C），含简并碱基时无法计算
+fn melting_temperature(seq: &[u8]) -> Option<f64> {
+    if seq.len() < 2 {
+        return None;
+    }
+    let terminal = |base: u8| match base {
+        b'G' | b'C' => Some((0.1, -2.8)),
+        b'A' | b'T' => Some((2.3, 4.1)),
+        _ => None,
+    };
+    let (mut dh, mut ds) = terminal(seq[0])?;
+    let (end_dh, end_ds) = terminal(seq[seq.len() - 1])?;
+    dh += end_dh;
+    ds += end_ds;
+    for pair in seq.windows(2) {
+        let (h, s) = nearest_neighbor(pair)?;
+        dh += h;
+        ds += s;
+    }
+    // 盐浓度校正
+    ds += 0.368 * (seq.len() - 1) as f64 * NA_CONCENTRATION.ln();
+    Some(dh * 1000.0 / (ds + 1.987 * (PRIMER_CONCENTRATION / 4.0).ln()) - 273.15)
+}
+
+fn gc_content(seq: &[u8]) -> f64 {
+    let gc = seq.iter().filter(|&&b| matches!(b, b'G' | b'C' | b'S')).count();
+    gc as f64 / seq.len().max(1) as f64 * 100.0
+}
+
+fn longest_homopolymer(seq: &[u8]) -> usize {
+    let mut longest = 0;
+    let mut i = 0;
+    while i < seq.len() {
+        let run = seq[i..].iter().take_while(|&&b| b == seq[i]).count();
+        longest = longest.max(run);
+        i += run;
+    }
+    longest
+}
+
+// 只有确定碱基之间才算互补
+fn is_complement(a: u8, b: u8) -> bool {
+    matches!((a, b), (b'A', b'T') | (b'T', b'A') | (b'C', b'G') | (b'G', b'C'))
+}
+
+// a 的 3' 端与 b 上任意位置完全互补的最长长度
+fn three_prime_complementarity(a: &[u8], b: &[u8]) -> usize {
+    (1..=a.len().min(b.len()))
+        .take_while(|&len| {
+            let suffix = &a[a.len() - len..];
+            // suffix 的反向互补序列出现在 b 中
+            b.windows(len).any(|w| w.iter().zip(suffix.iter().rev()).all(|(&x, &y)| is_complement(x, y)))
+        })
+        .last()
+        .unwrap_or(0)
+}
+
+// 最长的发夹茎（连续互补碱基对），环至少 3 个碱基
+fn longest_hairpin(seq: &[u8]) -> usize {
+    const MIN_LOOP: usize = 3;
+    let mut longest = 0;
+    for i in 0..seq.len() {
+        for j in (i + 1..seq.len()).rev() {
+            let mut stem = 0;
+            while i + stem < j - stem
+                && j - stem - (i + stem) > MIN_LOOP
+                && is_complement(seq[i + stem], seq[j - stem])
+            {
+                stem += 1;
+            }
+            longest = longest.max(stem);
+        }
+    }
+    longest
+}
+
+// 两个引物（含反向互补方向）之间的最小编辑距离，任一方向上以较短的序列作为 query
+fn primer_distance(a: &Primer, b: &Primer) -> usize {
+    let mut best = usize::MAX;
+    for target in [&b.seq, &b.rc_seq] {
+        let (query, target) = if a.seq.len() <= target.len() {
+            (a.seq.as_bytes(), target.as_bytes())
+        } else {
+            (target.as_bytes(), a.seq.as_bytes())
+        };
+        if let Some(r) = align_sequence(query, target, query.len() as i32) {
+            best = best.min(r.edit_distance as usize);
+        }
+    }
+    best
+}
+
+// 未通过的检查项，全部通过时为 PASS
+fn status(failed: &[Check]) -> String {
+    if failed.is_empty() {
+        "PASS".to_string()
+    } else {
+        failed.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(",")
+    }
+}
+
+struct PrimerCheck {
+    gc: f64,
+    tm: Option<f64>,
+    homopolymer: usize,
+    hairpin: usize,
+    self_dimer: usize,
+    // 与其他引物的最长 3' 端互补及对应的引物
+    cross_dimer: usize,
+    cross_partner: Option<usize>,
+    indistinguishable: Vec<usize>,
+    failed: Vec<Check>,
+}
+
+pub fn run(args: CheckPrimersArgs) -> Result<()> {
+    let options = PrimerOptions {
+        format: args.primer_format,
+        reference: args.reference.clone(),
+        max_errors: args.max_errors,
+        max_error_rate: args.max_error_rate,
+        seed_length: Some(0),
+        verify_prefilter: false,
+        allow_collisions: args.allow_primer_collisions,
+        size_tolerance: 0.0,
+    };
+    let primer_set = load_primers(&args.primers, &options).context("加载引物文件失败")?;
+    let primers = &primer_set.primers;
+    std::fs::create_dir_all(&args.outdir)?;
+
+    let mut checks: Vec<PrimerCheck> = primers
+        .iter()
+        .map(|p| {
+            let seq = p.seq.as_bytes();
+            PrimerCheck {
+                gc: gc_content(seq),
+                tm: melting_temperature(seq),
+                homopolymer: longest_homopolymer(seq),
+                hairpin: longest_hairpin(seq),
+                self_dimer: three_prime_complementarity(seq, seq),
+                cross_dimer: 0,
+                cross_partner: None,
+                indistinguishable: Vec::new(),
+                failed: Vec::new(),
+            }
+        })
+        .collect();
+
+    // 两两检查引物间二聚体和可区分性
+    let pairs_path = PathBuf::from(&args.outdir).join("primer_check_pairs.tsv");
+    let mut writer = std::io::BufWriter::new(
+        File::create(&pairs_path).with_context(|| format!("无法创建输出文件: {}", pairs_path.display()))?,
+    );
+    writeln!(writer, "Primer1\tPrimer2\tDimer_3p\tEdit_Distance\tIndistinguishable\tStatus\tFatal")?;
+    for i in 0..primers.len() {
+        for j in i + 1..primers.len() {
+            let (a, b) = (primers[i].seq.as_bytes(), primers[j].seq.as_bytes());
+            let dimer = three_prime_complementarity(a, b).max(three_prime_complementarity(b, a));
+            let distance = primer_distance(&primers[i], &primers[j]);
+            // 任一引物的最大错配数内能匹配另一个引物时，两者的读段无法区分
+            let limit = primers[i].max_errors.max(primers[j].max_errors).max(0) as usize;
+            let indistinguishable = distance <= limit;
+
+            for (k, other) in [(i, j), (j, i)] {
+                if dimer > checks[k].cross_dimer {
+                    checks[k].cross_dimer = dimer;
+                    checks[k].cross_partner = Some(other);
+                }
+                if indistinguishable {
+                    checks[k].indistinguishable.push(other);
+                }
+            }
+
+            let mut failed = Vec::new();
+            if dimer > args.max_dimer {
+                failed.push(Check::CrossDimer);
+            }
+            if indistinguishable {
+                failed.push(Check::Indistinguishable);
+            }
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                primers[i].name,
+                primers[j].name,
+                dimer,
+                distance,
+                indistinguishable,
+                status(&failed),
+                failed.iter().any(|c| args.fail_on.contains(c)),
+            )?;
+        }
+    }
+    writer.flush()?;
+
+    for check in &mut checks {
+        let tm_failed = check.tm.is_some_and(|tm| tm < args.min_tm || tm > args.max_tm);
+        let results = [
+            (Check::Gc, check.gc < args.min_gc || check.gc > args.max_gc),
+            (Check::Tm, tm_failed),
+            (Check::Homopolymer, check.homopolymer > args.max_homopolymer),
+            (Check::Hairpin, check.hairpin > args.max_hairpin),
+            (Check::SelfDimer, check.self_dimer > args.max_dimer),
+            (Check::CrossDimer, check.cross_dimer > args.max_dimer),
+            (Check::Indistinguishable, !check.indistinguishable.is_empty()),
+        ];
+        check.failed = results.iter().filter(|(_, failed)| *failed).map(|(c, _)| *c).collect();
+    }
+
+    let primers_path = PathBuf::from(&args.outdir).join("primer_check.tsv");
+    let mut writer = std::io::BufWriter::new(
+        File::create(&primers_path).with_context(|| format!("无法创建输出文件: {}", primers_path.display()))?,
+    );
+    writeln!(
+        writer,
+        "Primer\tSequence\tLength\tMax_Errors\tGC\tTm\tHomopolymer\tHairpin\tSelf_Dimer_3p\t\
+         Cross_Dimer_3p\tCross_Dimer_Partner\tIndistinguishable_From\tStatus\tFatal"
+    )?;
+    for (p, check) in primers.iter().zip(&checks) {
+        let names = |indices: &[usize]| {
+            if indices.is_empty() {
+                "-".to_string()
+            } else {
+                indices.iter().map(|&i| primers[i].name.as_str()).collect::<Vec<_>>().join(",")
+            }
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{:.1}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            p.name,
+            p.seq,
+            p.seq.len(),
+            p.max_errors,
+            check.gc,
+            check.tm.map_or("-".to_string(), |tm| format!("{:.1}", tm)),
+            check.homopolymer,
+            check.hairpin,
+            check.self_dimer,
+            check.cross_dimer,
+            check.cross_partner.map_or("-", |i| primers[i].name.as_str()),
+            names(&check.indistinguishable),
+            status(&check.failed),
+            check.failed.iter().any(|c| args.fail_on.contains(c)),
+        )?;
+    }
+    writer.flush()?;
+
+    println!("\n引物检查结果:");
+    let mut failures = 0;
+    for (p, check) in primers.iter().zip(&checks) {
+        if check.failed.is_empty() {
+            continue;
+        }
+        let fatal = check.failed.iter().any(|c| args.fail_on.contains(c));
+        if fatal {
+            failures += 1;
+        }
+        println!(
+            "{}{}: {}",
+            if fatal { "[失败] " } else { "[警告] " },
+            p.name,
+            check.failed.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(", ")
+        );
+    }
+    println!("共 {} 个引物，{} 个未通过检查", primers.len(), failures);
+    println!("结果已写入: {} 和 {}", primers_path.display(), pairs_path.display());
+
+    if failures > 0 {
+        anyhow::bail!("{} 个引物未通过检查", failures);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn melting_temperature_nearest_neighbor() {
+        // 按 SantaLucia 1998 参数手工计算：ΔH = -92.0 kcal/mol，ΔS = -262.53 cal/K·mol（含盐校正），
+        // Tm = -92000 / (-262.53 + 1.987 × ln(62.5 nM)) - 273.15 ≈ 38.2 °C
+        let tm = melting_temperature(b"ACGTACGTACGT").unwrap();
+        assert!((tm - 38.2).abs() < 0.05, "{}", tm);
+        // GC 含量越高 Tm 越高
+        let low = melting_temperature(b"ATATATATATATATATATAT").unwrap();
+        let high = melting_temperature(b"GCGCGCGCGCGCGCGCGCGC").unwrap();
+        assert!(low < high);
+        // 含简并碱基或过短时无法计算
+        assert_eq!(melting_temperature(b"ACGTNACGT"), None);
+        assert_eq!(melting_temperature(b"ACGTRACGT"), None);
+        assert_eq!(melting_temperature(b"A"), None);
+    }
+
+    #[test]
+    fn longest_hairpin_stem() {
+        // GGGC 与 GCCC 互补，中间环为 AAAA
+        assert_eq!(longest_hairpin(b"GGGCAAAAGCCC"), 4);
+        // 环恰好 3 个碱基时仍可形成发夹；只有 2 个碱基时茎缩短一个碱基对，使环满足最小长度
+        assert_eq!(longest_hairpin(b"GGGCAAAGCCC"), 4);
+        assert_eq!(longest_hairpin(b"GGGCAAGCCC"), 3);
+        assert_eq!(longest_hairpin(b"AAAAAAAAAA"), 0);
+        // 简并碱基不算互补
+        assert_eq!(longest_hairpin(b"NNNNAAAANNNN"), 0);
+    }
+
+    #[test]
+    fn three_prime_dimer() {
+        // 自身二聚体：3' 端 ACGT 的反向互补仍为 ACGT
+        assert_eq!(three_prime_complementarity(b"TTTTTACGT", b"TTTTTACGT"), 4);
+        // 3' 端 GGCC 与另一个引物中的 GGCC 互补
+        assert_eq!(three_prime_complementarity(b"AAAAAGGCC", b"TTGGCCGGG"), 4);
+        // 只有 3' 端末尾开始的互补才计入：CCCC 只在 a 的内部
+        assert_eq!(three_prime_complementarity(b"CCCCAAAAA", b"GGGGCCCCC"), 0);
+        assert_eq!(three_prime_complementarity(b"AAAAA", b"CCCCC"), 0);
+    }
+
+    #[test]
+    fn status_lists_failed_checks() {
+        assert_eq!(status(&[]), "PASS");
+        assert_eq!(status(&[Check::CrossDimer, Check::Indistinguishable]), "cross-dimer,indistinguishable");
+    }
+}
@@ -1,22 +1,50 @@
 // 移除不需要的导入
 use anyhow::{Context, Result};
-use clap::Parser;
-use edlib_rs::edlibrs::{edlibAlignRs, EdlibAlignConfigRs, EdlibAlignModeRs, EDLIB_STATUS_OK, EdlibAlignTaskRs};
+use clap::{Parser, Subcommand};
+use edlib_rs::edlibrs::{
+    edlibAlignRs, EdlibAlignConfigRs, EdlibAlignModeRs, EDLIB_STATUS_OK, EdlibAlignTaskRs, EdlibEqualityPairRs,
+    EDLIB_EDOP_MATCH, EDLIB_EDOP_INSERT, EDLIB_EDOP_DELETE, EDLIB_EDOP_MISMATCH,
+};
 use bio::alphabets::dna::revcomp;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use ahash::AHashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
 use std::time::Instant;
 use serde::Serialize;
-use std::path::PathBuf;
-use std::env::args;
+use std::path::{Path, PathBuf};
+use std::ops::Range;
 use rayon::prelude::*;
-use std::sync::mpsc;
+use std::sync::{mpsc, OnceLock};
 use std::thread;
+use primer_formats::PrimerFormat;
 
-// Args 结构保持不变
+mod check_primers;
+mod primer_formats;
+mod rebalance;
+mod report;
+
+// 不带子命令时执行读段分析；给出子命令时分析参数不再是必需参数
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Option<Args>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 根据统计结果计算引物浓度调整倍数
+    Rebalance(rebalance::RebalanceArgs),
+    /// 检查引物文件（GC、Tm、同聚物、二聚体、发夹、可区分性）
+    CheckPrimers(check_primers::CheckPrimersArgs),
+}
+
+// Args 结构保持不变
+#[derive(clap::Args, Debug)]
 struct Args {
     /// 输入的fastq.gz文件（单端测序）或者第一端序列文件（双端测序）
     #[arg(short, long)]
@@ -26,10 +54,29 @@ struct Args {
     #[arg(short = '2', long)]
     input2: Option<String>,
 
-    /// 引物序列文件(TSV格式：name\tsequence)
+    /// 引物序列文件(TSV格式：name\tsequence[\tmax_errors])，第三列可为单个引物指定最大错配数；
+    /// 也支持 FASTA、带表头的 CSV 和 ARTIC 风格的 BED 引物方案，见 --primer-format
     #[arg(short, long)]
     primers: String,
 
+    /// 引物文件格式，auto 按扩展名（.fa/.fasta、.csv、.bed）和内容自动判断
+    #[arg(long, value_enum, default_value = "auto")]
+    primer_format: PrimerFormat,
+
+    /// 参考基因组 FASTA 文件，BED 引物方案没有序列列时用于提取引物序列
+    #[arg(long)]
+    reference: Option<String>,
+
+    /// 扩增子面板文件(TSV格式：amplicon\tforward\treverse[\texpected_size\tpool])，提供后只评估声明的引物对；
+    /// expected_size 可以是单个长度或 min-max 范围。BED 引物方案自带扩增子，提供面板文件时以面板文件为准
+    #[arg(short = 'P', long)]
+    panel: Option<String>,
+
+    /// 面板中预期长度为单个值（以及 BED 引物方案中的扩增子长度）允许的相对偏差，如 0.1 表示 ±10%。
+    /// 声明了预期长度的扩增子，二聚体阈值为 min(--min-distance, (预期最小长度 - 两个引物长度) / 2)
+    #[arg(long, default_value = "0.1")]
+    size_tolerance: f64,
+
     /// 输出目录
     #[arg(short = 'O', long, default_value = "output")]
     outdir: String,
@@ -38,12 +85,45 @@ struct Args {
     #[arg(short = 'S', long)]
     sample: String,
 
+    /// 引物文件中存在重复名称、相同序列、互为反向互补或子串关系的引物时只给出警告而不报错
+    #[arg(long)]
+    allow_primer_collisions: bool,
+
     /// 最大允许错配数
     #[arg(short = 'e', long, default_value = "3")]
     max_errors: i32,
 
-    /// 判定为二聚体的最小距离
-    #[arg(short = 'd', long, default_value = "100")]
+    /// 按引物长度计算最大错配数（向下取整），如 0.1 表示每 10 个碱基允许 1 个错配；设置后代替 --max-errors
+    #[arg(long)]
+    max_error_rate: Option<f64>,
+
+    /// k-mer 预筛选的种子长度（默认按最短引物长度/(最大错配数+1)自动选择，0 表示关闭预筛选）
+    #[arg(short = 'k', long)]
+    seed_length: Option<usize>,
+
+    /// 对预筛选跳过的引物也进行比对，统计被漏掉的命中数（用于验证灵敏度）
+    #[arg(long)]
+    verify_prefilter: bool,
+
+    /// 正向引物只在读段前 N 个碱基中查找（锚定搜索）
+    #[arg(long)]
+    forward_window: Option<usize>,
+
+    /// 反向互补引物只在读段最后 N 个碱基中查找（锚定搜索）
+    #[arg(long)]
+    reverse_window: Option<usize>,
+
+    /// 引物 3' 端严格匹配区域的长度（0 表示不检查），该区域内的错误数超过 --three-prime-errors 的命中被丢弃
+    #[arg(long, default_value = "0")]
+    three_prime_length: usize,
+
+    /// 引物 3' 端区域内允许的最大错误数
+    #[arg(long, default_value = "0")]
+    three_prime_errors: usize,
+
+    /// 判定为二聚体的最小插入片段长度（正向引物末端到反向引物起始之间的碱基数），引物比对重叠时总是判定为二聚体。
+    /// 注意：旧版本中该参数为两个引物起始位置之间的距离、默认 100，沿用旧命令行时需要减去正向引物长度
+    #[arg(short = 'd', long, default_value = "80")]
     min_distance: usize,
 
     /// 最大输出序列数（0表示输出所有序列）
@@ -57,17 +137,103 @@ struct Args {
     /// 双端序列重叠区域最大错配率
     #[arg(short = 'm', long, default_value = "0.1")]
     max_mismatch_rate: f64,
+
+    /// 去除引物并输出插入片段的 FASTQ 文件({sample}_trimmed.fastq.gz)，按扩增子正链方向输出
+    #[arg(long)]
+    trim: bool,
+
+    /// 未能去除引物的读段的处理方式：drop 丢弃，separate 写入 {sample}_untrimmed.fastq.gz
+    #[arg(long, value_enum, default_value = "drop")]
+    untrimmed: UntrimmedMode,
+
+    /// 按引物对拆分读段，输出 {sample}_{F}_{R}.fastq.gz，二聚体和未分配读段单独输出
+    #[arg(long)]
+    demux: bool,
+
+    /// 多个引物对得分相同时的计数方式：first 计入第一个引物对，drop 不计入任何引物对，split 平均分配
+    #[arg(long, value_enum, default_value = "first")]
+    ambiguous: AmbiguousPolicy,
+
+    /// 读段数低于扩增子平均读段数该倍数时判定为脱落（dropout）
+    #[arg(long, default_value = "0.2")]
+    dropout_threshold: f64,
+
+    /// HTML 报告中预览的读段数
+    #[arg(long, default_value = "50")]
+    report_preview: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum UntrimmedMode {
+    Drop,
+    Separate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum AmbiguousPolicy {
+    First,
+    Drop,
+    Split,
+}
+
+impl AmbiguousPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AmbiguousPolicy::First => "first",
+            AmbiguousPolicy::Drop => "drop",
+            AmbiguousPolicy::Split => "split",
+        }
+    }
+}
+
+// 引物文件加载相关的参数
+#[derive(Debug, Clone)]
+struct PrimerOptions {
+    format: PrimerFormat,
+    reference: Option<String>,
+    max_errors: i32,
+    max_error_rate: Option<f64>,
+    // None 时自动选择种子长度，Some(0) 关闭预筛选
+    seed_length: Option<usize>,
+    verify_prefilter: bool,
+    allow_collisions: bool,
+    // BED 引物方案中扩增子长度允许的相对偏差
+    size_tolerance: f64,
+}
+
+// 输出相关的参数
+#[derive(Debug, Clone)]
+struct OutputOptions {
+    outdir: String,
+    sample: String,
+    max_output: usize,
+    // None 表示不输出去除引物后的序列
+    trim: Option<UntrimmedMode>,
+    demux: bool,
+    ambiguous: AmbiguousPolicy,
+    dropout_threshold: f64,
+    report_preview: usize,
 }
 
 // 其他结构体定义保持不变
 #[derive(Debug, Clone)]
 struct PrimerMatch {
     position: Option<usize>,
+    // 命中区域的结束位置（不含）
+    end: Option<usize>,
     errors: Option<usize>,
     found: bool,
+    // edlib 比对路径（引物为 query，读段为 target）
+    alignment: Option<Vec<u8>>,
 }
 
-#[derive(Debug)]
+impl PrimerMatch {
+    fn cigar(&self) -> String {
+        self.alignment.as_deref().map_or("-".to_string(), alignment_to_cigar)
+    }
+}
+
+#[derive(Debug, Clone)]
 struct ReadAnalysis {
     read_id: String,
     length: usize,
@@ -77,13 +243,246 @@ struct ReadAnalysis {
     f_match: PrimerMatch,
     r_match: PrimerMatch,
     distance: Option<usize>,
+    // 正向引物起始到反向引物末端的扩增子长度（含引物）、两引物之间的插入片段长度以及引物比对重叠的长度
+    amplicon_length: Option<usize>,
+    insert_length: Option<usize>,
+    overlap: Option<usize>,
     is_dimer: bool,
+    // 面板声明了预期长度范围时，按扩增子长度判定的产物大小分类
+    size_class: Option<SizeClass>,
+    amplicon: Option<String>,
+    unexpected_pair: bool,
+    internal_priming: bool,
+    category: ReadCategory,
+    prefilter: PrefilterCounts,
+    // 因 3' 端错误过多而被丢弃命中的引物（每个引物只记录一次）
+    three_prime_rejected: Vec<usize>,
+    // 读段中命中的所有引物（每个引物只记录一次），不限于所选引物对
+    primer_hits: Vec<ReadPrimerHit>,
+    // 存在得分与所选引物对相同的其他引物对
+    ambiguous: bool,
+    competing_pairs: Vec<CompetingPair>,
+}
+
+// 读段中单个引物的命中：as_forward/as_reverse 为正向序列/反向互补序列是否命中，errors 为两者中较小的编辑距离
+#[derive(Debug, Clone)]
+struct ReadPrimerHit {
+    primer: usize,
+    as_forward: bool,
+    as_reverse: bool,
+    errors: usize,
+}
+
+// 产物大小分类：符合预期、偏短、偏长或二聚体
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SizeClass {
+    Expected,
+    Short,
+    Long,
+    Dimer,
+}
+
+impl SizeClass {
+    const ALL: [SizeClass; 4] = [SizeClass::Expected, SizeClass::Short, SizeClass::Long, SizeClass::Dimer];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SizeClass::Expected => "expected",
+            SizeClass::Short => "short",
+            SizeClass::Long => "long",
+            SizeClass::Dimer => "dimer",
+        }
+    }
+}
+
+// 与所选引物对得分相同的引物对，引物按扩增子的正向、反向顺序记录
+#[derive(Debug, Clone)]
+struct CompetingPair {
+    amplicon: Option<String>,
+    forward: String,
+    reverse: String,
+}
+
+impl CompetingPair {
+    fn label(&self) -> String {
+        match &self.amplicon {
+            Some(name) => name.clone(),
+            None => format!("{}-{}", self.forward, self.reverse),
+        }
+    }
+}
+
+impl ReadAnalysis {
+    // 按引物对的正向/反向引物返回名称；负链读段上两者在读段中的位置是互换的。
+    // 没有找到引物对（包括只找到单个引物）时返回 "-"
+    fn pair_names(&self) -> (&str, &str) {
+        match self.strand {
+            '+' => (&self.f_primer, &self.r_primer),
+            '-' => (&self.r_primer, &self.f_primer),
+            _ => ("-", "-"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Primer {
+    name: String,
+    seq: String,
+    rc_seq: String,
+    // 该引物比对时允许的最大错误数，加载时根据引物文件、--max-error-rate 或 --max-errors 确定
+    max_errors: i32,
+}
+
+// 面板文件中声明的扩增子，forward_idx/reverse_idx 为引物在引物列表中的下标
+#[derive(Debug, Clone)]
+struct Amplicon {
+    name: String,
+    forward: String,
+    reverse: String,
+    forward_idx: usize,
+    reverse_idx: usize,
+    // BED 引物方案中替补引物参与的其他 (正向, 反向) 引物下标组合，命中时仍计入该扩增子
+    alternatives: Vec<(usize, usize)>,
+    // 预期扩增子长度范围（含引物），闭区间
+    size_range: Option<(usize, usize)>,
+    pool: Option<String>,
+}
+
+// 引物列表、面板声明的扩增子以及可选的 k-mer 预筛选索引
+struct PrimerSet {
+    primers: Vec<Primer>,
+    amplicons: Vec<Amplicon>,
+    index: Option<KmerIndex>,
+}
+
+// k-mer 预筛选索引：每个引物的正向序列和反向互补序列各取若干互不重叠的种子。
+// 比对目标用 primer_idx * 2 (+1 表示反向互补) 编号。
+// 种子数不少于该引物的 max_errors + 1 时，根据鸽巢原理，错误数不超过 max_errors 的命中必然包含
+// 至少一个完全匹配的种子；种子数不足的目标无法安全过滤，每条读段都会比对。
+struct KmerIndex {
+    seed_length: usize,
+    seeds: AHashMap<u64, Vec<usize>>,
+    always: Vec<usize>,
+    verify: bool,
+}
+
+impl KmerIndex {
+    fn new(primers: &[Primer], seed_length: usize, verify: bool) -> Self {
+        let mut seeds: AHashMap<u64, Vec<usize>> = AHashMap::new();
+        let mut always = Vec::new();
+
+        for (i, primer) in primers.iter().enumerate() {
+            let required = primer.max_errors.max(0) as usize + 1;
+            for (target, seq) in [(i * 2, &primer.seq), (i * 2 + 1, &primer.rc_seq)] {
+                let primer_seeds = Self::pick_seeds(seq.as_bytes(), seed_length);
+                if primer_seeds.len() < required {
+                    always.push(target);
+                    continue;
+                }
+                for kmer in primer_seeds {
+                    let targets = seeds.entry(kmer).or_default();
+                    if !targets.contains(&target) {
+                        targets.push(target);
+                    }
+                }
+            }
+        }
+
+        KmerIndex { seed_length, seeds, always, verify }
+    }
+
+    // 从左到右贪心选取互不重叠、只含 ACGT 的种子（简并碱基无法精确索引）
+    fn pick_seeds(seq: &[u8], seed_length: usize) -> Vec<u64> {
+        let mut seeds = Vec::new();
+        let mut start = 0;
+        while start + seed_length <= seq.len() {
+            match encode_kmer(&seq[start..start + seed_length]) {
+                Some(kmer) => {
+                    seeds.push(kmer);
+                    start += seed_length;
+                },
+                None => start += 1,
+            }
+        }
+        seeds
+    }
+
+    // 标记读段中种子命中的比对目标
+    fn candidates(&self, seq: &[u8], targets: &mut [bool]) {
+        for &t in &self.always {
+            targets[t] = true;
+        }
+
+        let mask = if self.seed_length >= 32 { u64::MAX } else { (1u64 << (2 * self.seed_length)) - 1 };
+        let mut kmer = 0u64;
+        let mut valid = 0;
+        for &base in seq {
+            match encode_base(base) {
+                Some(code) => {
+                    kmer = ((kmer << 2) | code) & mask;
+                    valid += 1;
+                },
+                None => valid = 0,
+            }
+            if valid >= self.seed_length {
+                if let Some(hit) = self.seeds.get(&kmer) {
+                    for &t in hit {
+                        targets[t] = true;
+                    }
+                }
+            }
+        }
+    }
+
+    // 无法建立索引（每条读段都要比对）的引物名称
+    fn unindexed_primers(&self, primers: &[Primer]) -> Vec<String> {
+        let mut names: Vec<String> = self.always.iter().map(|&t| primers[t / 2].name.clone()).collect();
+        names.dedup();
+        names
+    }
+}
+
+fn encode_base(base: u8) -> Option<u64> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+fn encode_kmer(kmer: &[u8]) -> Option<u64> {
+    kmer.iter().try_fold(0u64, |acc, &b| encode_base(b).map(|code| (acc << 2) | code))
 }
 
 #[derive(Debug, Clone)]
 struct AlignmentResult {
     edit_distance: i32,
     position: usize,
+    end: usize,
+    alignment: Vec<u8>,
+}
+
+// 单条读段分析所需的参数
+#[derive(Debug, Clone)]
+struct AnalysisOptions {
+    min_distance: usize,
+    forward_window: Option<usize>,
+    reverse_window: Option<usize>,
+    // 引物 3' 端最后 three_prime_length 个碱基内最多允许 three_prime_errors 个错误，长度为 0 时不检查
+    three_prime_length: usize,
+    three_prime_errors: usize,
+}
+
+impl AnalysisOptions {
+    fn is_anchored(&self) -> bool {
+        self.forward_window.is_some() || self.reverse_window.is_some()
+    }
+
+    fn checks_three_prime(&self) -> bool {
+        self.three_prime_length > 0
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -325,7 +724,115 @@ fn merge_paired_reads(
     })
 }
 
+// 读段分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ReadCategory {
+    // 面板中声明的引物对（无面板时为任意引物对）
+    OnTarget,
+    // 面板外的非预期引物对
+    OffTargetPair,
+    PrimerDimer,
+    // 引物对只在锚定窗口之外命中
+    InternalPriming,
+    SinglePrimer,
+    // 找到多个引物命中，但无法组成方向和顺序正确的引物对
+    Chimera,
+    NoPrimer,
+}
+
+impl ReadCategory {
+    const ALL: [ReadCategory; 7] = [
+        ReadCategory::OnTarget,
+        ReadCategory::OffTargetPair,
+        ReadCategory::PrimerDimer,
+        ReadCategory::InternalPriming,
+        ReadCategory::SinglePrimer,
+        ReadCategory::Chimera,
+        ReadCategory::NoPrimer,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReadCategory::OnTarget => "on_target",
+            ReadCategory::OffTargetPair => "off_target_pair",
+            ReadCategory::PrimerDimer => "primer_dimer",
+            ReadCategory::InternalPriming => "internal_priming",
+            ReadCategory::SinglePrimer => "single_primer",
+            ReadCategory::Chimera => "chimera",
+            ReadCategory::NoPrimer => "no_primer",
+        }
+    }
+}
+
+// 有面板时只统计声明的扩增子，否则统计观察到的引物对（不含未找到引物对的读段）
+fn coverage_uniformity(pairs: &[PrimerPairStat], has_panel: bool, dropout_threshold: f64) -> Option<UniformityStat> {
+    let pairs: Vec<&PrimerPairStat> = pairs
+        .iter()
+        .filter(|p| if has_panel { p.amplicon.is_some() } else { p.forward_primer != "-" })
+        .collect();
+    if pairs.is_empty() {
+        return None;
+    }
+
+    let mut counts: Vec<f64> = pairs.iter().map(|p| p.assigned).collect();
+    counts.sort_by(|a, b| a.total_cmp(b));
+    let n = counts.len() as f64;
+    let mean = counts.iter().sum::<f64>() / n;
+    let variance = counts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / n;
+    // 线性插值的百分位数
+    let quantile = |q: f64| {
+        let pos = q * (counts.len() - 1) as f64;
+        let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+        counts[lower] + (counts[upper] - counts[lower]) * (pos - lower as f64)
+    };
+    // 没有读段的扩增子总是计为脱落，所有扩增子都没有读段时不会因均值为 0 而被视为均一
+    let within = |fold: f64| counts.iter().filter(|&&c| c > 0.0 && c >= mean * fold).count() as f64 / n * 100.0;
+    let p20 = quantile(0.2);
+
+    Some(UniformityStat {
+        basis: if has_panel { "panel" } else { "observed" },
+        amplicons: counts.len(),
+        mean_reads: mean,
+        median_reads: quantile(0.5),
+        cv: if mean > 0.0 { variance.sqrt() / mean } else { 0.0 },
+        within_0_2x: within(0.2),
+        within_0_5x: within(0.5),
+        fold_80_penalty: (p20 > 0.0).then(|| mean / p20),
+        dropout_threshold,
+        dropouts: pairs
+            .iter()
+            .filter(|p| p.assigned == 0.0 || p.assigned < mean * dropout_threshold)
+            .map(|p| p.amplicon.clone().unwrap_or_else(|| format!("{}-{}", p.forward_primer, p.reverse_primer)))
+            .collect(),
+    })
+}
+
+// 按策略分配的读段数可能是小数，整数时不显示小数位
+fn format_count(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn percentage(count: usize, total: usize) -> f64 {
+    if total > 0 {
+        (count as f64 / total as f64) * 100.0
+    } else {
+        0.0
+    }
+}
+
 // 统计相关的结构体
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PairKey {
+    amplicon: Option<String>,
+    forward: String,
+    reverse: String,
+    unexpected: bool,
+}
+
 #[derive(Debug, Default)]
 struct Statistics {
     total_reads: usize,
@@ -333,7 +840,200 @@ struct Statistics {
     plus_strand: usize,
     minus_strand: usize,
     dimer_count: usize,
-    primer_pairs: HashMap<(String, String), usize>,
+    unexpected_pair_count: usize,
+    internal_priming_count: usize,
+    single_primer_count: usize,
+    ambiguous_count: usize,
+    // 引物名称 -> (只找到正向命中的读段数, 只找到反向互补命中的读段数)
+    single_primers: HashMap<String, (usize, usize)>,
+    categories: HashMap<ReadCategory, usize>,
+    primer_pairs: HashMap<PairKey, PairCounts>,
+    prefilter: PrefilterCounts,
+    reads_without_candidates: usize,
+    distances: LengthHistogram,
+    read_lengths: LengthHistogram,
+    // 引物下标 -> 命中计数
+    primer_hits: HashMap<usize, PrimerHitCounts>,
+    // 引物名称 -> 结合位点错配谱
+    mismatch_profiles: HashMap<String, MismatchProfile>,
+    // 引物下标 -> 因 3' 端错误过多被丢弃命中的读段数
+    three_prime_rejected: HashMap<usize, usize>,
+    three_prime_rejected_reads: usize,
+}
+
+// 单个引物对的计数：count 为选中该引物对的读段数，ambiguous 为该引物对参与得分并列的读段数，
+// assigned 为按 --ambiguous 策略分配的读段数
+#[derive(Debug, Default, Clone)]
+struct PairCounts {
+    count: usize,
+    ambiguous: usize,
+    assigned: f64,
+    categories: HashMap<ReadCategory, usize>,
+    size_classes: HashMap<SizeClass, usize>,
+    distances: LengthHistogram,
+    read_lengths: LengthHistogram,
+}
+
+// 单个引物的命中计数，每条读段只计一次：reads 为命中该引物的读段数，as_forward 为引物正向序列命中，
+// as_reverse 为反向互补序列命中（两个方向都命中的读段两者都计）；按读段所在链（+/-，未组成引物对为 ?）
+// 分别计数；errors 为读段中该引物的最小编辑距离 -> 读段数
+#[derive(Debug, Default, Clone)]
+struct PrimerHitCounts {
+    reads: usize,
+    as_forward: usize,
+    as_reverse: usize,
+    plus_strand: usize,
+    minus_strand: usize,
+    unpaired: usize,
+    errors: BTreeMap<usize, usize>,
+}
+
+// 长度 -> 读段数，按长度排序以便输出和计算中位数
+#[derive(Debug, Default, Clone)]
+struct LengthHistogram {
+    counts: BTreeMap<usize, usize>,
+}
+
+impl LengthHistogram {
+    fn add(&mut self, length: usize) {
+        *self.counts.entry(length).or_insert(0) += 1;
+    }
+
+    fn summary(&self) -> LengthStat {
+        let total: usize = self.counts.values().sum();
+        let sum: usize = self.counts.iter().map(|(length, count)| length * count).sum();
+        // 读段数相同时取较短的长度
+        let mode = self.counts
+            .iter()
+            .max_by(|(la, a), (lb, b)| a.cmp(b).then_with(|| lb.cmp(la)))
+            .map(|(&length, _)| length);
+        LengthStat {
+            count: total,
+            mean: if total > 0 { sum as f64 / total as f64 } else { 0.0 },
+            median: self.median(total),
+            mode,
+            min: self.counts.keys().next().copied(),
+            max: self.counts.keys().next_back().copied(),
+            histogram: self.counts.clone(),
+        }
+    }
+
+    // 读段数为偶数时取中间两个长度的平均值
+    fn median(&self, total: usize) -> Option<f64> {
+        if total == 0 {
+            return None;
+        }
+        let nth = |n: usize| {
+            let mut seen = 0;
+            self.counts.iter().find_map(|(&length, &count)| {
+                seen += count;
+                (seen > n).then_some(length)
+            })
+        };
+        let upper = nth(total / 2)?;
+        if total % 2 == 1 {
+            Some(upper as f64)
+        } else {
+            Some((nth(total / 2 - 1)? + upper) as f64 / 2.0)
+        }
+    }
+}
+
+// 单条读段的预筛选计数：比对的目标数、跳过的目标数、确认命中数以及验证模式下漏掉的命中数
+#[derive(Debug, Default, Clone, Copy)]
+struct PrefilterCounts {
+    candidates: usize,
+    skipped: usize,
+    confirmed: usize,
+    missed: usize,
+}
+
+// 单个引物结合位点的错配谱，位置按引物 5' -> 3' 方向计数。
+// 反向互补命中的读段碱基会先取互补，使期望碱基与观测碱基都在引物方向上
+#[derive(Debug, Clone)]
+struct MismatchProfile {
+    seq: Vec<u8>,
+    hits: usize,
+    positions: Vec<PositionCounts>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct PositionCounts {
+    matches: usize,
+    mismatches: usize,
+    // 引物碱基在读段中缺失（CIGAR 中的 I）
+    insertions: usize,
+    // 读段在该位置之前多出碱基（CIGAR 中的 D）
+    deletions: usize,
+    // 观测碱基 -> 次数，仅统计错配
+    substitutions: BTreeMap<char, usize>,
+}
+
+impl MismatchProfile {
+    fn new(primer: &Primer) -> Self {
+        MismatchProfile {
+            seq: primer.seq.as_bytes().to_vec(),
+            hits: 0,
+            positions: vec![PositionCounts::default(); primer.seq.len()],
+        }
+    }
+
+    // target 为读段中命中的区域；reverse 表示比对使用的是引物反向互补序列
+    fn add(&mut self, alignment: &[u8], target: &[u8], reverse: bool) {
+        let len = self.seq.len();
+        if len == 0 {
+            return;
+        }
+        self.hits += 1;
+        let (mut qi, mut ti) = (0, 0);
+        for &op in alignment {
+            let pos = if reverse { len - 1 - qi.min(len - 1) } else { qi.min(len - 1) };
+            let counts = &mut self.positions[pos];
+            match op {
+                EDLIB_EDOP_MATCH => {
+                    counts.matches += 1;
+                    qi += 1;
+                    ti += 1;
+                },
+                EDLIB_EDOP_MISMATCH => {
+                    let base = target.get(ti).copied().unwrap_or(b'N');
+                    let observed = if reverse { complement(base) } else { base };
+                    counts.mismatches += 1;
+                    *counts.substitutions.entry(observed.to_ascii_uppercase() as char).or_insert(0) += 1;
+                    qi += 1;
+                    ti += 1;
+                },
+                EDLIB_EDOP_INSERT => {
+                    counts.insertions += 1;
+                    qi += 1;
+                },
+                EDLIB_EDOP_DELETE => {
+                    counts.deletions += 1;
+                    ti += 1;
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+fn complement(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        other => other,
+    }
+}
+
+impl PrefilterCounts {
+    fn add(&mut self, other: &PrefilterCounts) {
+        self.candidates += other.candidates;
+        self.skipped += other.skipped;
+        self.confirmed += other.confirmed;
+        self.missed += other.missed;
+    }
 }
 
 #[derive(Serialize)]
@@ -346,58 +1046,446 @@ struct StatisticsOutput {
     minus_strand: usize,
     dimer_count: usize,
     dimer_rate: f64,
+    unexpected_pair_count: usize,
+    unexpected_pair_rate: f64,
+    internal_priming_count: usize,
+    internal_priming_rate: f64,
+    single_primer_count: usize,
+    single_primer_rate: f64,
+    ambiguous_count: usize,
+    ambiguous_rate: f64,
+    ambiguous_policy: &'static str,
+    single_primers: Vec<SinglePrimerStat>,
+    primer_hits: Vec<PrimerHitStat>,
+    distance_lengths: LengthStat,
+    read_lengths: LengthStat,
+    categories: Vec<CategoryStat>,
     primer_pairs: Vec<PrimerPairStat>,
+    prefilter: Option<PrefilterStat>,
+    trimming: Option<TrimStat>,
+    demultiplex: Option<Vec<DemuxFileStat>>,
+    mismatch_profiles: Vec<MismatchProfileStat>,
+    three_prime: Option<ThreePrimeStat>,
+    uniformity: Option<UniformityStat>,
+}
+
+// 扩增子覆盖均一性，读段数按 --ambiguous 策略分配后计算；
+// within_0_2x/within_0_5x 为读段数不低于均值 0.2/0.5 倍的扩增子比例（百分比）
+#[derive(Serialize)]
+struct UniformityStat {
+    // panel 表示基于面板声明的扩增子，observed 表示基于观察到的引物对
+    basis: &'static str,
+    amplicons: usize,
+    mean_reads: f64,
+    median_reads: f64,
+    cv: f64,
+    within_0_2x: f64,
+    within_0_5x: f64,
+    // 均值 / 第 20 百分位数，第 20 百分位数为 0 时无法计算
+    fold_80_penalty: Option<f64>,
+    dropout_threshold: f64,
+    // 读段数低于均值 dropout_threshold 倍或没有读段的扩增子
+    dropouts: Vec<String>,
+}
+
+// 3' 端严格匹配规则的设置及被拒绝的读段数
+#[derive(Serialize)]
+struct ThreePrimeStat {
+    length: usize,
+    max_errors: usize,
+    rejected_reads: usize,
+    primers: Vec<ThreePrimeRejectStat>,
+}
+
+#[derive(Serialize)]
+struct ThreePrimeRejectStat {
+    primer: String,
+    rejected_reads: usize,
+}
+
+// 引物结合位点错配谱：positions 从引物 5' 端开始编号（1 起），
+// substitutions 为期望碱基 -> 观测碱基 -> 次数
+#[derive(Serialize)]
+struct MismatchProfileStat {
+    primer: String,
+    hits: usize,
+    positions: Vec<PositionStat>,
+    substitutions: BTreeMap<char, BTreeMap<char, usize>>,
+}
+
+#[derive(Serialize)]
+struct PositionStat {
+    position: usize,
+    base: char,
+    matches: usize,
+    mismatches: usize,
+    insertions: usize,
+    deletions: usize,
+    mismatch_rate: f64,
+    substitutions: BTreeMap<char, usize>,
+}
+
+#[derive(Serialize)]
+struct DemuxFileStat {
+    file: String,
+    reads: usize,
+}
+
+#[derive(Serialize)]
+struct TrimStat {
+    trimmed_reads: usize,
+    untrimmed_reads: usize,
+    // 未去除引物的读段是否写入 {sample}_untrimmed.fastq.gz
+    untrimmed_written: bool,
+}
+
+// 只找到单个引物的读段：forward_only 为引物正向序列位于读段 5' 端，reverse_only 为反向互补序列位于 3' 端
+#[derive(Serialize)]
+struct SinglePrimerStat {
+    primer: String,
+    forward_only: usize,
+    reverse_only: usize,
+}
+
+// errors[i] 为编辑距离为 i 的命中数，长度为该引物最大错配数 + 1
+#[derive(Serialize)]
+struct PrimerHitStat {
+    primer: String,
+    reads: usize,
+    as_forward: usize,
+    as_reverse: usize,
+    plus_strand: usize,
+    minus_strand: usize,
+    unpaired: usize,
+    mean_errors: f64,
+    errors: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct PrefilterStat {
+    seed_length: usize,
+    indexed_seeds: usize,
+    unindexed_primers: Vec<String>,
+    candidate_alignments: usize,
+    skipped_alignments: usize,
+    confirmed_hits: usize,
+    // 候选比对中确认命中的比例
+    hit_rate: f64,
+    // 被跳过的比对占全部比对的比例
+    skip_rate: f64,
+    reads_without_candidates: usize,
+    // 仅在 --verify-prefilter 时统计，应始终为 0
+    missed_hits: Option<usize>,
 }
 
 #[derive(Serialize)]
 struct PrimerPairStat {
+    amplicon: Option<String>,
     forward_primer: String,
     reverse_primer: String,
+    size_range: Option<(usize, usize)>,
+    pool: Option<String>,
+    unexpected: bool,
     count: usize,
+    ambiguous: usize,
+    assigned: f64,
+    // 按 assigned 计算
     percentage: f64,
+    categories: BTreeMap<&'static str, usize>,
+    // 仅在面板声明了预期长度时输出
+    size_classes: Option<BTreeMap<&'static str, usize>>,
+    // 引物间距离（Distance）和读段长度的分布
+    distance_lengths: LengthStat,
+    read_lengths: LengthStat,
 }
 
-// 结果处理器
-struct AnalysisWriter {
-    writer: flate2::write::GzEncoder<std::io::BufWriter<File>>,
-    sample_name: String,
-    output_dir: PathBuf,
+// 长度分布：histogram 为长度 -> 读段数
+#[derive(Serialize)]
+struct LengthStat {
     count: usize,
-    max_output: usize,
-    stats: Statistics,
+    mean: f64,
+    median: Option<f64>,
+    mode: Option<usize>,
+    min: Option<usize>,
+    max: Option<usize>,
+    histogram: BTreeMap<usize, usize>,
 }
 
-impl AnalysisWriter {
-    fn new(output_file: &str, sample_name: &str, max_output: usize) -> Result<Self> {
-        let output_path = PathBuf::from(output_file);
-        let output_dir = output_path.parent()
-            .ok_or_else(|| anyhow::anyhow!("无法获取输出目录"))?
-            .to_path_buf();
+#[derive(Serialize)]
+struct CategoryStat {
+    category: &'static str,
+    count: usize,
+    percentage: f64,
+}
 
-        let file = File::create(output_file)?;
-        let buf_writer = std::io::BufWriter::with_capacity(64 * 1024, file);
-        let mut writer = flate2::write::GzEncoder::new(buf_writer, flate2::Compression::default());
-        
-        // 移除表头中的 F_Alignment 和 R_Alignment 列
-        writeln!(
-            writer,
-            "Read_ID\tLength\tStrand\tF_Primer\tR_Primer\tF_Found\tF_Pos\tF_Errors\t\
-             R_Found\tR_Pos\tR_Errors\tDistance\tIs_Dimer"
-        )?;
+type GzWriter = flate2::write::GzEncoder<std::io::BufWriter<File>>;
 
-        Ok(AnalysisWriter {
-            writer,
-            sample_name: sample_name.to_string(),
-            output_dir,
+fn create_gz_writer(path: &Path) -> Result<GzWriter> {
+    let file = File::create(path)
+        .with_context(|| format!("无法创建输出文件: {}", path.display()))?;
+    let buf_writer = std::io::BufWriter::with_capacity(64 * 1024, file);
+    Ok(flate2::write::GzEncoder::new(buf_writer, flate2::Compression::default()))
+}
+
+fn write_fastq<W: Write>(writer: &mut W, id: &str, seq: &[u8], qual: &[u8]) -> Result<()> {
+    writeln!(writer, "@{}", id)?;
+    writer.write_all(seq)?;
+    writer.write_all(b"\n+\n")?;
+    writer.write_all(qual)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+// 两个引物之间的插入片段范围；只有找到引物对的正常产物（on_target/off_target_pair）才会去除引物
+fn insert_range(analysis: &ReadAnalysis) -> Option<Range<usize>> {
+    if !matches!(analysis.category, ReadCategory::OnTarget | ReadCategory::OffTargetPair) {
+        return None;
+    }
+    let start = analysis.f_match.end?;
+    let end = analysis.r_match.position?;
+    (start < end).then_some(start..end)
+}
+
+// 去除引物后的 FASTQ 输出
+struct TrimmedOutput {
+    trimmed: GzWriter,
+    untrimmed: Option<GzWriter>,
+    trimmed_count: usize,
+    untrimmed_count: usize,
+}
+
+impl TrimmedOutput {
+    fn new(output_dir: &Path, sample_name: &str, mode: UntrimmedMode) -> Result<Self> {
+        let trimmed = create_gz_writer(&output_dir.join(format!("{}_trimmed.fastq.gz", sample_name)))?;
+        let untrimmed = match mode {
+            UntrimmedMode::Drop => None,
+            UntrimmedMode::Separate => Some(create_gz_writer(
+                &output_dir.join(format!("{}_untrimmed.fastq.gz", sample_name)),
+            )?),
+        };
+
+        Ok(TrimmedOutput {
+            trimmed,
+            untrimmed,
+            trimmed_count: 0,
+            untrimmed_count: 0,
+        })
+    }
+
+    fn write(&mut self, analysis: &ReadAnalysis, record: &FastqRecord) -> Result<()> {
+        match insert_range(analysis) {
+            Some(range) => {
+                let seq = &record.seq[range.clone()];
+                let qual = &record.qual[range];
+                // 负链读段转换到扩增子正链方向
+                if analysis.strand == '-' {
+                    let qual: Vec<u8> = qual.iter().rev().copied().collect();
+                    write_fastq(&mut self.trimmed, &record.id, &revcomp(seq), &qual)?;
+                } else {
+                    write_fastq(&mut self.trimmed, &record.id, seq, qual)?;
+                }
+                self.trimmed_count += 1;
+            },
+            None => {
+                if let Some(writer) = self.untrimmed.as_mut() {
+                    write_fastq(writer, &record.id, &record.seq, &record.qual)?;
+                }
+                self.untrimmed_count += 1;
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.trimmed.try_finish()?;
+        if let Some(writer) = self.untrimmed.as_mut() {
+            writer.try_finish()?;
+        }
+        Ok(())
+    }
+}
+
+// 拆分输出中每个文件的缓冲上限，超过后压缩并追加到文件
+const DEMUX_BUFFER_SIZE: usize = 256 * 1024;
+
+// 按引物对拆分的 FASTQ 输出。
+// 引物对可能有数百个，为避免同时打开大量文件，每个输出只在内存中缓存未压缩的记录，
+// 缓冲区满时压缩为一个新的 gzip 成员追加到文件后立即关闭。多成员 gzip 文件可被
+// gzip/zcat 等工具正常读取
+struct DemuxOutput {
+    output_dir: PathBuf,
+    sample_name: String,
+    buffers: HashMap<String, Vec<u8>>,
+    // 每个输出文件的读段数
+    counts: HashMap<String, usize>,
+    // 引物对 (正向, 反向) -> 文件标签
+    pair_labels: HashMap<(String, String), String>,
+}
+
+impl DemuxOutput {
+    fn new(output_dir: &Path, sample_name: &str) -> Self {
+        DemuxOutput {
+            output_dir: output_dir.to_path_buf(),
+            sample_name: sample_name.to_string(),
+            buffers: HashMap::new(),
+            counts: HashMap::new(),
+            pair_labels: HashMap::new(),
+        }
+    }
+
+    // 输出文件名中的标签：正常产物按引物对，二聚体和其他读段各自单独输出。
+    // 不同引物对的标签可能相同（如 A_B + C 与 A + B_C，或名称中的特殊字符都被替换为 _），
+    // 此时后出现的引物对依次加 _2、_3 等后缀，避免读段被合并到同一个文件
+    fn label(&mut self, analysis: &ReadAnalysis) -> String {
+        match analysis.category {
+            ReadCategory::OnTarget | ReadCategory::OffTargetPair => {
+                let (forward, reverse) = analysis.pair_names();
+                let key = (forward.to_string(), reverse.to_string());
+                if let Some(label) = self.pair_labels.get(&key) {
+                    return label.clone();
+                }
+
+                let base = format!("{}_{}", sanitize_file_name(forward), sanitize_file_name(reverse));
+                let mut label = base.clone();
+                let mut suffix = 1;
+                while self.pair_labels.values().any(|l| *l == label) {
+                    suffix += 1;
+                    label = format!("{}_{}", base, suffix);
+                }
+                if suffix > 1 {
+                    eprintln!("警告: 引物对 {} + {} 的拆分文件名与其他引物对重复，使用 {}", forward, reverse, label);
+                }
+                self.pair_labels.insert(key, label.clone());
+                label
+            },
+            ReadCategory::PrimerDimer => "dimer".to_string(),
+            _ => "unassigned".to_string(),
+        }
+    }
+
+    fn file_path(&self, label: &str) -> PathBuf {
+        self.output_dir.join(format!("{}_{}.fastq.gz", self.sample_name, label))
+    }
+
+    fn write(&mut self, analysis: &ReadAnalysis, record: &FastqRecord) -> Result<()> {
+        let label = self.label(analysis);
+        if !self.counts.contains_key(&label) {
+            self.prepare(&label)?;
+        }
+        *self.counts.entry(label.clone()).or_insert(0) += 1;
+
+        let buffer = self.buffers.entry(label.clone()).or_default();
+        write_fastq(buffer, &record.id, &record.seq, &record.qual)?;
+        if buffer.len() >= DEMUX_BUFFER_SIZE {
+            self.flush(&label)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, label: &str) -> Result<()> {
+        let path = self.file_path(label);
+        let buffer = match self.buffers.get_mut(label) {
+            Some(buffer) => std::mem::take(buffer),
+            None => return Ok(()),
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("无法写入拆分文件: {}", path.display()))?;
+        let mut encoder = flate2::write::GzEncoder::new(std::io::BufWriter::new(file), flate2::Compression::default());
+        encoder.write_all(&buffer)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let labels: Vec<String> = self.buffers.keys().cloned().collect();
+        for label in labels {
+            self.flush(&label)?;
+        }
+        Ok(())
+    }
+
+    // 第一次写入前截断同名的旧文件，避免追加到上一次运行的结果后面
+    fn prepare(&self, label: &str) -> Result<()> {
+        let path = self.file_path(label);
+        File::create(&path)
+            .with_context(|| format!("无法创建拆分文件: {}", path.display()))?;
+        Ok(())
+    }
+}
+
+// 将引物名称转换为安全的文件名
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '.') { c } else { '_' })
+        .collect()
+}
+
+// 结果处理器
+struct AnalysisWriter {
+    writer: flate2::write::GzEncoder<std::io::BufWriter<File>>,
+    sample_name: String,
+    output_dir: PathBuf,
+    count: usize,
+    max_output: usize,
+    stats: Statistics,
+    trimmed_output: Option<TrimmedOutput>,
+    demux_output: Option<DemuxOutput>,
+    // HTML 报告预览用的前若干条分析结果及其序列
+    preview: Vec<(ReadAnalysis, FastqRecord)>,
+    preview_limit: usize,
+    ambiguous_policy: AmbiguousPolicy,
+    dropout_threshold: f64,
+    // 扩增子名称 -> 主引物对，通过替补引物命中的读段也计入主引物对
+    amplicon_primers: HashMap<String, (String, String)>,
+}
+
+impl AnalysisWriter {
+    fn new(output_file: &str, sample_name: &str, max_output: usize, preview_limit: usize) -> Result<Self> {
+        let output_path = PathBuf::from(output_file);
+        let output_dir = output_path.parent()
+            .ok_or_else(|| anyhow::anyhow!("无法获取输出目录"))?
+            .to_path_buf();
+
+        let file = File::create(output_file)?;
+        let buf_writer = std::io::BufWriter::with_capacity(64 * 1024, file);
+        let mut writer = flate2::write::GzEncoder::new(buf_writer, flate2::Compression::default());
+        
+        // 移除表头中的 F_Alignment 和 R_Alignment 列
+        writeln!(
+            writer,
+            "Read_ID\tLength\tStrand\tF_Primer\tR_Primer\tF_Found\tF_Pos\tF_Errors\t\
+             R_Found\tR_Pos\tR_Errors\tDistance\tIs_Dimer\tAmplicon\tUnexpected_Pair\tInternal_Priming\tCategory\tF_CIGAR\tR_CIGAR\t\
+             Ambiguous\tCompeting_Pairs\tAmplicon_Length\tInsert_Length\tOverlap\tSize_Class"
+        )?;
+
+        Ok(AnalysisWriter {
+            writer,
+            sample_name: sample_name.to_string(),
+            output_dir,
             count: 0,
             max_output,
             stats: Statistics::default(),
+            trimmed_output: None,
+            demux_output: None,
+            preview: Vec::with_capacity(preview_limit),
+            preview_limit,
+            ambiguous_policy: AmbiguousPolicy::First,
+            dropout_threshold: 0.2,
+            amplicon_primers: HashMap::new(),
         })
     }
 
-    fn process(&mut self, analysis: &ReadAnalysis) -> Result<()> {
+    fn process(&mut self, analysis: &ReadAnalysis, record: &FastqRecord) -> Result<()> {
         // 更新统计信息部分保持不变
         self.stats.total_reads += 1;
+        self.stats.read_lengths.add(analysis.length);
+        if let Some(distance) = analysis.distance {
+            self.stats.distances.add(distance);
+        }
         
         if analysis.f_match.found && analysis.r_match.found {
             self.stats.both_primers_found += 1;
@@ -412,9 +1500,118 @@ impl AnalysisWriter {
         if analysis.is_dimer {
             self.stats.dimer_count += 1;
         }
+
+        if analysis.unexpected_pair {
+            self.stats.unexpected_pair_count += 1;
+        }
+
+        if analysis.internal_priming {
+            self.stats.internal_priming_count += 1;
+        }
+
+        *self.stats.categories.entry(analysis.category).or_insert(0) += 1;
+
+        if analysis.category == ReadCategory::SinglePrimer {
+            self.stats.single_primer_count += 1;
+            if analysis.f_match.found {
+                self.stats.single_primers.entry(analysis.f_primer.clone()).or_default().0 += 1;
+            } else {
+                self.stats.single_primers.entry(analysis.r_primer.clone()).or_default().1 += 1;
+            }
+        }
         
-        let pair = (analysis.f_primer.clone(), analysis.r_primer.clone());
-        *self.stats.primer_pairs.entry(pair).or_insert(0) += 1;
+        let (forward, reverse) = match analysis.amplicon.as_ref().and_then(|name| self.amplicon_primers.get(name)) {
+            Some((forward, reverse)) => (forward.as_str(), reverse.as_str()),
+            None => analysis.pair_names(),
+        };
+        let pair = PairKey {
+            amplicon: analysis.amplicon.clone(),
+            forward: forward.to_string(),
+            reverse: reverse.to_string(),
+            unexpected: analysis.unexpected_pair,
+        };
+        // 得分并列的读段按策略分配给所选引物对及其竞争引物对
+        let weight = match (analysis.ambiguous, self.ambiguous_policy) {
+            (false, _) | (true, AmbiguousPolicy::First) => 1.0,
+            (true, AmbiguousPolicy::Drop) => 0.0,
+            (true, AmbiguousPolicy::Split) => 1.0 / (analysis.competing_pairs.len() + 1) as f64,
+        };
+        let pair_counts = self.stats.primer_pairs.entry(pair).or_default();
+        pair_counts.count += 1;
+        pair_counts.assigned += weight;
+        *pair_counts.categories.entry(analysis.category).or_insert(0) += 1;
+        if let Some(size_class) = analysis.size_class {
+            *pair_counts.size_classes.entry(size_class).or_insert(0) += 1;
+        }
+        pair_counts.read_lengths.add(analysis.length);
+        if let Some(distance) = analysis.distance {
+            pair_counts.distances.add(distance);
+        }
+
+        if analysis.ambiguous {
+            self.stats.ambiguous_count += 1;
+            pair_counts.ambiguous += 1;
+            let competing_weight = if self.ambiguous_policy == AmbiguousPolicy::Split { weight } else { 0.0 };
+            for competing in &analysis.competing_pairs {
+                let counts = self.stats.primer_pairs.entry(PairKey {
+                    amplicon: competing.amplicon.clone(),
+                    forward: competing.forward.clone(),
+                    reverse: competing.reverse.clone(),
+                    unexpected: analysis.unexpected_pair,
+                }).or_default();
+                counts.ambiguous += 1;
+                counts.assigned += competing_weight;
+            }
+        }
+
+        for (name, m, reverse) in [
+            (&analysis.f_primer, &analysis.f_match, false),
+            (&analysis.r_primer, &analysis.r_match, true),
+        ] {
+            if let (Some(start), Some(end), Some(ops)) = (m.position, m.end, &m.alignment) {
+                if let Some(profile) = self.stats.mismatch_profiles.get_mut(name) {
+                    profile.add(ops, &record.seq[start..end], reverse);
+                }
+            }
+        }
+
+        // 引物命中统计基于读段中的全部命中，包括嵌合读段和所选引物对之外的命中
+        for hit in &analysis.primer_hits {
+            let hits = self.stats.primer_hits.entry(hit.primer).or_default();
+            hits.reads += 1;
+            hits.as_forward += hit.as_forward as usize;
+            hits.as_reverse += hit.as_reverse as usize;
+            match analysis.strand {
+                '+' => hits.plus_strand += 1,
+                '-' => hits.minus_strand += 1,
+                _ => hits.unpaired += 1,
+            }
+            *hits.errors.entry(hit.errors).or_insert(0) += 1;
+        }
+
+        if !analysis.three_prime_rejected.is_empty() {
+            self.stats.three_prime_rejected_reads += 1;
+            for &idx in &analysis.three_prime_rejected {
+                *self.stats.three_prime_rejected.entry(idx).or_insert(0) += 1;
+            }
+        }
+
+        self.stats.prefilter.add(&analysis.prefilter);
+        if analysis.prefilter.candidates == 0 {
+            self.stats.reads_without_candidates += 1;
+        }
+
+        if let Some(trimmed_output) = self.trimmed_output.as_mut() {
+            trimmed_output.write(analysis, record)?;
+        }
+
+        if let Some(demux_output) = self.demux_output.as_mut() {
+            demux_output.write(analysis, record)?;
+        }
+
+        if self.preview.len() < self.preview_limit {
+            self.preview.push((analysis.clone(), record.clone()));
+        }
 
         // 如果达到最大输出数量，只收集统计信息不写入文件
         if self.max_output > 0 && self.count >= self.max_output {
@@ -424,7 +1621,7 @@ impl AnalysisWriter {
         // 写入分析结果，移除 f_alignment 和 r_alignment
         writeln!(
             self.writer,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
             analysis.read_id,
             analysis.length,
             analysis.strand,
@@ -438,48 +1635,250 @@ impl AnalysisWriter {
             analysis.r_match.errors.map_or("-".to_string(), |e| e.to_string()),
             analysis.distance.map_or("-".to_string(), |d| d.to_string()),
             analysis.is_dimer,
+            analysis.amplicon.as_deref().unwrap_or("-"),
+            analysis.unexpected_pair,
+            analysis.internal_priming,
+            analysis.category.as_str(),
+            analysis.f_match.cigar(),
+            analysis.r_match.cigar(),
+            analysis.ambiguous,
+            if analysis.competing_pairs.is_empty() {
+                "-".to_string()
+            } else {
+                analysis.competing_pairs.iter().map(|c| c.label()).collect::<Vec<_>>().join(",")
+            },
+            analysis.amplicon_length.map_or("-".to_string(), |l| l.to_string()),
+            analysis.insert_length.map_or("-".to_string(), |l| l.to_string()),
+            analysis.overlap.map_or("-".to_string(), |o| o.to_string()),
+            analysis.size_class.map_or("-", |c| c.as_str()),
         )?;
         
         self.count += 1;
         Ok(())
     }
 
-    fn get_statistics(&self) -> StatisticsOutput {
-        StatisticsOutput {
+    fn get_statistics(&self, primer_set: &PrimerSet, options: &AnalysisOptions) -> StatisticsOutput {
+        // 面板中声明但没有读段的扩增子也要列出（计数为 0）
+        let mut primer_pairs = self.stats.primer_pairs.clone();
+        for amp in &primer_set.amplicons {
+            primer_pairs.entry(PairKey {
+                amplicon: Some(amp.name.clone()),
+                forward: amp.forward.clone(),
+                reverse: amp.reverse.clone(),
+                unexpected: false,
+            }).or_default();
+        }
+        // 按读段数从多到少排序，保证重复运行时输出一致
+        let mut primer_pairs: Vec<(PairKey, PairCounts)> = primer_pairs.into_iter().collect();
+        primer_pairs.sort_by(|(ka, a), (kb, b)| {
+            b.assigned.total_cmp(&a.assigned)
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| (&ka.amplicon, &ka.forward, &ka.reverse).cmp(&(&kb.amplicon, &kb.forward, &kb.reverse)))
+        });
+
+        let mut output = StatisticsOutput {
             sample_name: self.sample_name.clone(),
             total_reads: self.stats.total_reads,
             both_primers_found: self.stats.both_primers_found,
-            success_rate: if self.stats.total_reads > 0 {
-                (self.stats.both_primers_found as f64 / self.stats.total_reads as f64) * 100.0
-            } else {
-                0.0
-            },
+            success_rate: percentage(self.stats.both_primers_found, self.stats.total_reads),
             plus_strand: self.stats.plus_strand,
             minus_strand: self.stats.minus_strand,
             dimer_count: self.stats.dimer_count,
-            dimer_rate: if self.stats.total_reads > 0 {
-                (self.stats.dimer_count as f64 / self.stats.total_reads as f64) * 100.0
-            } else {
-                0.0
-            },
-            primer_pairs: self.stats.primer_pairs
+            dimer_rate: percentage(self.stats.dimer_count, self.stats.total_reads),
+            unexpected_pair_count: self.stats.unexpected_pair_count,
+            unexpected_pair_rate: percentage(self.stats.unexpected_pair_count, self.stats.total_reads),
+            internal_priming_count: self.stats.internal_priming_count,
+            internal_priming_rate: percentage(self.stats.internal_priming_count, self.stats.total_reads),
+            single_primer_count: self.stats.single_primer_count,
+            single_primer_rate: percentage(self.stats.single_primer_count, self.stats.total_reads),
+            ambiguous_count: self.stats.ambiguous_count,
+            ambiguous_rate: percentage(self.stats.ambiguous_count, self.stats.total_reads),
+            ambiguous_policy: self.ambiguous_policy.as_str(),
+            distance_lengths: self.stats.distances.summary(),
+            read_lengths: self.stats.read_lengths.summary(),
+            // 没有命中的引物也列出，便于发现完全失效的引物
+            primer_hits: primer_set.primers
                 .iter()
-                .map(|((f, r), count)| PrimerPairStat {
-                    forward_primer: f.clone(),
-                    reverse_primer: r.clone(),
-                    count: *count,
-                    percentage: if self.stats.total_reads > 0 {
-                        (*count as f64 / self.stats.total_reads as f64) * 100.0
-                    } else {
-                        0.0
-                    },
+                .enumerate()
+                .map(|(i, p)| {
+                    let hits = self.stats.primer_hits.get(&i).cloned().unwrap_or_default();
+                    let reads = hits.reads;
+                    let total_errors: usize = hits.errors.iter().map(|(e, n)| e * n).sum();
+                    let mut errors = vec![0; p.max_errors.max(0) as usize + 1];
+                    for (&e, &n) in &hits.errors {
+                        if e >= errors.len() {
+                            errors.resize(e + 1, 0);
+                        }
+                        errors[e] += n;
+                    }
+                    PrimerHitStat {
+                        primer: p.name.clone(),
+                        reads,
+                        as_forward: hits.as_forward,
+                        as_reverse: hits.as_reverse,
+                        plus_strand: hits.plus_strand,
+                        minus_strand: hits.minus_strand,
+                        unpaired: hits.unpaired,
+                        mean_errors: if reads > 0 { total_errors as f64 / reads as f64 } else { 0.0 },
+                        errors,
+                    }
                 })
                 .collect(),
-        }
+            single_primers: primer_set.primers
+                .iter()
+                .filter_map(|p| {
+                    self.stats.single_primers.get(&p.name).map(|&(forward_only, reverse_only)| SinglePrimerStat {
+                        primer: p.name.clone(),
+                        forward_only,
+                        reverse_only,
+                    })
+                })
+                .collect(),
+            categories: ReadCategory::ALL
+                .iter()
+                .map(|category| {
+                    let count = self.stats.categories.get(category).copied().unwrap_or(0);
+                    CategoryStat {
+                        category: category.as_str(),
+                        count,
+                        percentage: percentage(count, self.stats.total_reads),
+                    }
+                })
+                .collect(),
+            primer_pairs: primer_pairs
+                .iter()
+                .map(|(pair, counts)| {
+                    let amp = pair.amplicon.as_ref()
+                        .and_then(|name| primer_set.amplicons.iter().find(|a| &a.name == name));
+                    PrimerPairStat {
+                        amplicon: pair.amplicon.clone(),
+                        forward_primer: pair.forward.clone(),
+                        reverse_primer: pair.reverse.clone(),
+                        size_range: amp.and_then(|a| a.size_range),
+                        pool: amp.and_then(|a| a.pool.clone()),
+                        unexpected: pair.unexpected,
+                        count: counts.count,
+                        ambiguous: counts.ambiguous,
+                        assigned: counts.assigned,
+                        percentage: if self.stats.total_reads > 0 {
+                            counts.assigned / self.stats.total_reads as f64 * 100.0
+                        } else {
+                            0.0
+                        },
+                        categories: counts.categories
+                            .iter()
+                            .map(|(category, count)| (category.as_str(), *count))
+                            .collect(),
+                        size_classes: amp.and_then(|a| a.size_range).map(|_| {
+                            SizeClass::ALL
+                                .iter()
+                                .map(|c| (c.as_str(), counts.size_classes.get(c).copied().unwrap_or(0)))
+                                .collect()
+                        }),
+                        distance_lengths: counts.distances.summary(),
+                        read_lengths: counts.read_lengths.summary(),
+                    }
+                })
+                .collect(),
+            prefilter: primer_set.index.as_ref().map(|index| {
+                let counts = &self.stats.prefilter;
+                let total = counts.candidates + counts.skipped;
+                PrefilterStat {
+                    seed_length: index.seed_length,
+                    indexed_seeds: index.seeds.len(),
+                    unindexed_primers: index.unindexed_primers(&primer_set.primers),
+                    candidate_alignments: counts.candidates,
+                    skipped_alignments: counts.skipped,
+                    confirmed_hits: counts.confirmed,
+                    hit_rate: percentage(counts.confirmed, counts.candidates),
+                    skip_rate: percentage(counts.skipped, total),
+                    reads_without_candidates: self.stats.reads_without_candidates,
+                    missed_hits: index.verify.then_some(counts.missed),
+                }
+            }),
+            trimming: self.trimmed_output.as_ref().map(|t| TrimStat {
+                trimmed_reads: t.trimmed_count,
+                untrimmed_reads: t.untrimmed_count,
+                untrimmed_written: t.untrimmed.is_some(),
+            }),
+            demultiplex: self.demux_output.as_ref().map(|d| {
+                let mut files: Vec<DemuxFileStat> = d.counts
+                    .iter()
+                    .map(|(label, reads)| DemuxFileStat {
+                        file: d.file_path(label)
+                            .file_name()
+                            .map(|f| f.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                        reads: *reads,
+                    })
+                    .collect();
+                files.sort_by(|a, b| a.file.cmp(&b.file));
+                files
+            }),
+            mismatch_profiles: primer_set.primers
+                .iter()
+                .filter_map(|p| self.stats.mismatch_profiles.get(&p.name).map(|profile| (p, profile)))
+                .map(|(p, profile)| {
+                    let mut substitutions: BTreeMap<char, BTreeMap<char, usize>> = BTreeMap::new();
+                    let positions = profile.positions
+                        .iter()
+                        .enumerate()
+                        .map(|(i, counts)| {
+                            let base = profile.seq[i].to_ascii_uppercase() as char;
+                            for (&observed, &count) in &counts.substitutions {
+                                *substitutions.entry(base).or_default().entry(observed).or_insert(0) += count;
+                            }
+                            PositionStat {
+                                position: i + 1,
+                                base,
+                                matches: counts.matches,
+                                mismatches: counts.mismatches,
+                                insertions: counts.insertions,
+                                deletions: counts.deletions,
+                                mismatch_rate: percentage(counts.mismatches, profile.hits),
+                                substitutions: counts.substitutions.clone(),
+                            }
+                        })
+                        .collect();
+                    MismatchProfileStat {
+                        primer: p.name.clone(),
+                        hits: profile.hits,
+                        positions,
+                        substitutions,
+                    }
+                })
+                .collect(),
+            three_prime: options.checks_three_prime().then(|| ThreePrimeStat {
+                length: options.three_prime_length,
+                max_errors: options.three_prime_errors,
+                rejected_reads: self.stats.three_prime_rejected_reads,
+                primers: primer_set.primers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| ThreePrimeRejectStat {
+                        primer: p.name.clone(),
+                        rejected_reads: self.stats.three_prime_rejected.get(&i).copied().unwrap_or(0),
+                    })
+                    .collect(),
+            }),
+            uniformity: None,
+        };
+        output.uniformity = coverage_uniformity(
+            &output.primer_pairs,
+            !primer_set.amplicons.is_empty(),
+            self.dropout_threshold,
+        );
+        output
     }
 
     fn finalize(&mut self) -> Result<()> {
         self.writer.try_finish()?;
+        if let Some(trimmed_output) = self.trimmed_output.as_mut() {
+            trimmed_output.finish()?;
+        }
+        if let Some(demux_output) = self.demux_output.as_mut() {
+            demux_output.finish()?;
+        }
         Ok(())
     }
 
@@ -489,55 +1888,378 @@ impl AnalysisWriter {
         std::fs::write(&stats_path, stats_json)?;
         Ok(())
     }
+
+    // 每个引物每个位置一行，便于按错配率筛选结合位点变异
+    fn save_mismatch_profile(&self, stats: &StatisticsOutput) -> Result<()> {
+        let path = self.output_dir.join(format!("{}_primer_mismatch_profile.tsv", self.sample_name));
+        let file = File::create(&path)
+            .with_context(|| format!("无法创建输出文件: {}", path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        writeln!(
+            writer,
+            "Primer\tPosition\tBase\tHits\tMatches\tMismatches\tInsertions\tDeletions\tMismatch_Rate\tSubstitutions"
+        )?;
+        for profile in &stats.mismatch_profiles {
+            for pos in &profile.positions {
+                let substitutions = if pos.substitutions.is_empty() {
+                    "-".to_string()
+                } else {
+                    pos.substitutions
+                        .iter()
+                        .map(|(observed, count)| format!("{}>{}:{}", pos.base, observed, count))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                };
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.2}\t{}",
+                    profile.primer,
+                    pos.position,
+                    pos.base,
+                    profile.hits,
+                    pos.matches,
+                    pos.mismatches,
+                    pos.insertions,
+                    pos.deletions,
+                    pos.mismatch_rate,
+                    substitutions,
+                )?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // 长度分布的长表格式：每个引物对（all 为全部读段）、每种长度一行
+    fn save_length_histogram(&self, stats: &StatisticsOutput) -> Result<()> {
+        let path = self.output_dir.join(format!("{}_length_histogram.tsv", self.sample_name));
+        let file = File::create(&path)
+            .with_context(|| format!("无法创建输出文件: {}", path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        writeln!(writer, "Amplicon\tForward_Primer\tReverse_Primer\tMetric\tLength\tCount")?;
+
+        let mut groups = vec![("all", "-", "-", &stats.distance_lengths, &stats.read_lengths)];
+        for pair in &stats.primer_pairs {
+            groups.push((
+                pair.amplicon.as_deref().unwrap_or("-"),
+                pair.forward_primer.as_str(),
+                pair.reverse_primer.as_str(),
+                &pair.distance_lengths,
+                &pair.read_lengths,
+            ));
+        }
+        for (amplicon, forward, reverse, distances, read_lengths) in groups {
+            for (metric, lengths) in [("distance", distances), ("read_length", read_lengths)] {
+                for (length, count) in &lengths.histogram {
+                    writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}", amplicon, forward, reverse, metric, length, count)?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn save_report(&self, stats: &StatisticsOutput, primer_set: &PrimerSet) -> Result<()> {
+        let report_path = self.output_dir.join(format!("{}_report.html", self.sample_name));
+        let html = report::render_report(stats, &self.preview, primer_set);
+        std::fs::write(&report_path, html)?;
+        Ok(())
+    }
 }
 
 
-fn load_primers(primer_file: &str) -> Result<HashMap<String, (String, String)>> {
-    let file = File::open(primer_file)?;
-    let reader = BufReader::new(file);
-    let mut primers = HashMap::new();
+// 引物按名称排序，保证引物对的检查顺序一致；BED 引物方案中的扩增子写入 PrimerSet::amplicons
+fn load_primers(primer_file: &str, options: &PrimerOptions) -> Result<PrimerSet> {
+    let PrimerOptions { max_errors, max_error_rate, seed_length, verify_prefilter, allow_collisions, .. } = *options;
+    if let Some(rate) = max_error_rate {
+        if !(0.0..1.0).contains(&rate) {
+            anyhow::bail!("最大错配率必须在 [0, 1) 范围内: {}", rate);
+        }
+    }
 
-    for line in reader.lines() {
-        let line = line.context("无法读取引物文件行，可能存在编码问题")?;
-        if line.trim().is_empty() || line.starts_with('#') {
+    let (records, scheme) = primer_formats::read_primer_file(
+        primer_file,
+        options.format,
+        options.reference.as_deref(),
+    )?;
+
+    // (行号, 名称, 序列, 反向互补序列, 最大错配数)，按文件顺序保存以便报告冲突
+    let mut entries: Vec<(usize, String, String, String, i32)> = Vec::new();
+    for record in records {
+        let (name, seq) = (record.name, record.seq);
+        if !seq.bytes().all(|c| is_iupac_base(c.to_ascii_uppercase())) {
+            eprintln!("警告: 跳过包含无效字符的序列: {} - {}", name, seq);
             continue;
         }
-        
-        let parts: Vec<&str> = line.split('\t').collect();
-        if parts.len() >= 2 {
-            let name = parts[0].trim_start_matches('\u{feff}').trim();
-            let seq = parts[1].trim();
-            
-            if !seq.chars().all(|c| matches!(c.to_ascii_uppercase(), 'A' | 'T' | 'G' | 'C' | 'N')) {
-                eprintln!("警告: 跳过包含无效字符的序列: {} - {}", name, seq);
-                continue;
-            }
-            
-            let seq = seq.to_uppercase();
-            let rc_seq = String::from_utf8_lossy(&revcomp(seq.as_bytes())).into_owned();
-            primers.insert(name.to_string(), (seq.to_uppercase(), rc_seq.to_uppercase()));
-        } else {
-            eprintln!("警告: 跳过格式不正确的行: {}", line);
+
+        // 文件中为该引物指定的最大错配数优先于 --max-error-rate 和 --max-errors
+        let limit = record.max_errors.unwrap_or_else(|| match max_error_rate {
+            Some(rate) => (seq.len() as f64 * rate).floor() as i32,
+            None => max_errors,
+        });
+
+        // revcomp 支持 IUPAC 简并碱基（如 R <-> Y, K <-> M）
+        let seq = seq.to_uppercase();
+        let rc_seq = String::from_utf8_lossy(&revcomp(seq.as_bytes())).into_owned();
+        entries.push((record.line_no, name, seq, rc_seq.to_uppercase(), limit));
+    }
+
+    let collisions = find_primer_collisions(&entries);
+    if !collisions.is_empty() {
+        if !allow_collisions {
+            anyhow::bail!(
+                "引物文件中存在 {} 处重复或冲突（可使用 --allow-primer-collisions 忽略）:\n  {}",
+                collisions.len(),
+                collisions.join("\n  ")
+            );
+        }
+        for collision in &collisions {
+            eprintln!("警告: {}", collision);
         }
     }
 
+    // 名称重复时后面的行覆盖前面的行
+    let mut primers = HashMap::new();
+    for (_, name, seq, rc_seq, limit) in entries {
+        primers.insert(name, (seq, rc_seq, limit));
+    }
+
     if primers.is_empty() {
         anyhow::bail!("未能加载任何有效的引物序列");
     }
 
     println!("成功加载 {} 个引物序列", primers.len());
-    Ok(primers)
+
+    let mut primers: Vec<Primer> = primers
+        .into_iter()
+        .map(|(name, (seq, rc_seq, max_errors))| Primer { name, seq, rc_seq, max_errors })
+        .collect();
+    primers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let min_limit = primers.iter().map(|p| p.max_errors).min().unwrap_or(0);
+    let max_limit = primers.iter().map(|p| p.max_errors).max().unwrap_or(0);
+    if min_limit != max_limit {
+        println!("引物最大错配数: {} - {}", min_limit, max_limit);
+    }
+
+    let seed_length = match seed_length {
+        Some(len) if len > 32 => anyhow::bail!("种子长度不能超过 32: {}", len),
+        Some(len) => len,
+        // 取所有引物中 长度/(最大错配数+1) 的最小值，保证每个引物都能切出足够的种子
+        None => primers
+            .iter()
+            .map(|p| p.seq.len() / (p.max_errors.max(0) as usize + 1))
+            .min()
+            .unwrap_or(0)
+            .min(32),
+    };
+    let index = if seed_length > 0 {
+        let index = KmerIndex::new(&primers, seed_length, verify_prefilter);
+        println!(
+            "k-mer 预筛选索引: 种子长度 {}，{} 个种子，{} 个比对目标无法索引",
+            seed_length,
+            index.seeds.len(),
+            index.always.len()
+        );
+        Some(index)
+    } else {
+        None
+    };
+
+    let amplicons = scheme
+        .into_iter()
+        .map(|amp| {
+            let find_primer = |primer: &str| {
+                primers.iter()
+                    .position(|p| p.name == primer)
+                    .ok_or_else(|| anyhow::anyhow!("扩增子 {} 的引物 {} 未能加载", amp.name, primer))
+            };
+            let alternatives = amp.alternatives
+                .iter()
+                .map(|(forward, reverse)| Ok((find_primer(forward)?, find_primer(reverse)?)))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Amplicon {
+                forward_idx: find_primer(&amp.forward)?,
+                reverse_idx: find_primer(&amp.reverse)?,
+                alternatives,
+                size_range: Some((
+                    size_range(amp.size.0, options.size_tolerance).0,
+                    size_range(amp.size.1, options.size_tolerance).1,
+                )),
+                name: amp.name,
+                forward: amp.forward,
+                reverse: amp.reverse,
+                pool: amp.pool,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(PrimerSet {
+        primers,
+        amplicons,
+        index,
+    })
+}
+
+// 单个预期长度按相对偏差展开为范围
+fn size_range(size: usize, size_tolerance: f64) -> (usize, usize) {
+    let delta = (size as f64 * size_tolerance).round() as usize;
+    (size.saturating_sub(delta), size + delta)
 }
 
+// 检查名称重复、序列相同、互为反向互补以及一个引物是另一个引物（或其反向互补序列）的子串
+fn find_primer_collisions(entries: &[(usize, String, String, String, i32)]) -> Vec<String> {
+    let mut collisions = Vec::new();
+    for (i, (line_a, name_a, seq_a, _, _)) in entries.iter().enumerate() {
+        for (line_b, name_b, seq_b, rc_b, _) in &entries[i + 1..] {
+            let (a, b) = (format!("第 {} 行 {}", line_a, name_a), format!("第 {} 行 {}", line_b, name_b));
+            if name_a == name_b {
+                collisions.push(format!("{} 与{} 名称重复", a, b));
+            }
+            if seq_a == seq_b {
+                collisions.push(format!("{} 与{} 序列相同", a, b));
+            } else if seq_a == rc_b {
+                collisions.push(format!("{} 与{} 互为反向互补序列", a, b));
+            } else if seq_b.contains(seq_a.as_str()) || rc_b.contains(seq_a.as_str()) {
+                collisions.push(format!("{} 是{}（或其反向互补序列）的子串", a, b));
+            } else if seq_a.contains(seq_b.as_str()) || seq_a.contains(rc_b.as_str()) {
+                collisions.push(format!("{} 是{}（或其反向互补序列）的子串", b, a));
+            }
+        }
+    }
+    collisions
+}
 
+fn load_panel(panel_file: &str, primers: &[Primer], size_tolerance: f64) -> Result<Vec<Amplicon>> {
+    let file = File::open(panel_file)?;
+    let reader = BufReader::new(file);
+    let mut amplicons: Vec<Amplicon> = Vec::new();
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.context("无法读取面板文件行，可能存在编码问题")?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split('\t').map(|p| p.trim()).collect();
+        if parts.len() < 3 {
+            anyhow::bail!("面板文件第 {} 行格式不正确，至少需要 amplicon/forward/reverse 三列: {}", line_no + 1, line);
+        }
+
+        let name = parts[0].trim_start_matches('\u{feff}');
+        let (forward, reverse) = (parts[1], parts[2]);
+        let find_primer = |primer: &str| {
+            primers.iter()
+                .position(|p| p.name == primer)
+                .ok_or_else(|| anyhow::anyhow!("面板文件第 {} 行引用了未知引物: {}", line_no + 1, primer))
+        };
+        let forward_idx = find_primer(forward)?;
+        let reverse_idx = find_primer(reverse)?;
+        if forward_idx == reverse_idx {
+            anyhow::bail!("面板文件第 {} 行扩增子 {} 的正向和反向引物相同: {}", line_no + 1, name, forward);
+        }
+        if amplicons.iter().any(|a| a.name == name) {
+            anyhow::bail!("面板文件第 {} 行扩增子名称重复: {}", line_no + 1, name);
+        }
+
+        // 预期长度可以是 min-max 范围，单个值时按 --size-tolerance 展开
+        let size_range = match parts.get(3) {
+            Some(s) if !s.is_empty() && *s != "-" => {
+                let invalid = || anyhow::anyhow!("面板文件第 {} 行预期长度无效: {}", line_no + 1, s);
+                let range = match s.split_once('-') {
+                    Some((min, max)) => (
+                        min.trim().parse::<usize>().map_err(|_| invalid())?,
+                        max.trim().parse::<usize>().map_err(|_| invalid())?,
+                    ),
+                    None => size_range(s.parse::<usize>().map_err(|_| invalid())?, size_tolerance),
+                };
+                if range.0 > range.1 {
+                    return Err(invalid());
+                }
+                Some(range)
+            },
+            _ => None,
+        };
+        let pool = parts.get(4)
+            .filter(|p| !p.is_empty() && **p != "-")
+            .map(|p| p.to_string());
+
+        amplicons.push(Amplicon {
+            name: name.to_string(),
+            forward: forward.to_string(),
+            reverse: reverse.to_string(),
+            forward_idx,
+            reverse_idx,
+            alternatives: Vec::new(),
+            size_range,
+            pool,
+        });
+    }
+
+    if amplicons.is_empty() {
+        anyhow::bail!("面板文件中没有声明任何扩增子");
+    }
+
+    Ok(amplicons)
+}
+
+
+
+// IUPAC 简并碱基及其可匹配的碱基
+const IUPAC_CODES: &[(u8, &[u8])] = &[
+    (b'R', b"AG"),
+    (b'Y', b"CT"),
+    (b'S', b"GC"),
+    (b'W', b"AT"),
+    (b'K', b"GT"),
+    (b'M', b"AC"),
+    (b'B', b"CGT"),
+    (b'D', b"AGT"),
+    (b'H', b"ACT"),
+    (b'V', b"ACG"),
+    (b'N', b"ACGT"),
+];
+
+fn is_iupac_base(c: u8) -> bool {
+    matches!(c, b'A' | b'T' | b'G' | b'C') || IUPAC_CODES.iter().any(|(code, _)| *code == c)
+}
+
+// 传给 edlib 的等价碱基对，使引物中的简并位点与兼容碱基比对时不计错配
+fn iupac_equalities() -> &'static [EdlibEqualityPairRs] {
+    static EQUALITIES: OnceLock<Vec<EdlibEqualityPairRs>> = OnceLock::new();
+    EQUALITIES.get_or_init(|| {
+        IUPAC_CODES
+            .iter()
+            .flat_map(|(code, bases)| {
+                bases.iter().map(move |base| EdlibEqualityPairRs {
+                    first: *code as std::os::raw::c_char,
+                    second: *base as std::os::raw::c_char,
+                })
+            })
+            .collect()
+    })
+}
+
+// edlib 的等价碱基对是双向的，读段中的 N 等简并碱基也会与任意引物碱基匹配。
+// 比对前将读段转为大写（与 k-mer 预筛选一致，软屏蔽的小写碱基仍可比对），并将非 ACGT 碱基替换为
+// 没有等价碱基对的占位符，只让引物一侧的简并碱基起通配作用
+const READ_MASK: u8 = b'*';
+
+fn mask_read(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .map(|b| match b.to_ascii_uppercase() {
+            b @ (b'A' | b'C' | b'G' | b'T') => b,
+            _ => READ_MASK,
+        })
+        .collect()
+}
 
 fn align_sequence(query: &[u8], target: &[u8], max_errors: i32) -> Option<AlignmentResult> {
-    let additional_equalities: Vec<edlib_rs::edlibrs::EdlibEqualityPairRs> = Vec::new();
     let config = EdlibAlignConfigRs {
         k: max_errors,
         mode: EdlibAlignModeRs::EDLIB_MODE_HW,
         task: EdlibAlignTaskRs::EDLIB_TASK_PATH,
-        additionalequalities: &additional_equalities,
+        additionalequalities: iupac_equalities(),
     };
 
     let result = edlibAlignRs(query, target, &config);
@@ -554,96 +2276,382 @@ fn align_sequence(query: &[u8], target: &[u8], max_errors: i32) -> Option<Alignm
     }
 
     let start_pos = start_locations[0] as usize;
+    // edlib 的结束位置包含在比对区域内
+    let end_pos = end_locations[0] as usize + 1;
     
     Some(AlignmentResult {
         edit_distance: result.editDistance,
         position: start_pos,
+        end: end_pos,
+        alignment: result.alignment.unwrap_or_default(),
     })
 }
 
+// 将 edlib 比对路径转换为扩展 CIGAR 字符串：= 匹配，X 错配，I 引物中多出的碱基，D 读段中多出的碱基
+fn alignment_to_cigar(alignment: &[u8]) -> String {
+    let code = |op: u8| match op {
+        EDLIB_EDOP_MATCH => '=',
+        EDLIB_EDOP_MISMATCH => 'X',
+        EDLIB_EDOP_INSERT => 'I',
+        EDLIB_EDOP_DELETE => 'D',
+        _ => '?',
+    };
+
+    let mut cigar = String::new();
+    let mut i = 0;
+    while i < alignment.len() {
+        let op = alignment[i];
+        let run = alignment[i..].iter().take_while(|&&o| o == op).count();
+        cigar.push_str(&format!("{}{}", run, code(op)));
+        i += run;
+    }
+    cigar
+}
+
+// 统计比对中落在引物 3' 端最后 length 个碱基内的错误数。
+// 正向序列的 3' 端在比对末尾，反向互补序列的 3' 端在比对开头；
+// 读段中多出的碱基只有位于该区域两个碱基之间时才计入
+fn three_prime_errors(alignment: &[u8], query_len: usize, reverse: bool, length: usize) -> usize {
+    let region = if reverse {
+        0..length.min(query_len)
+    } else {
+        query_len.saturating_sub(length)..query_len
+    };
+    let mut qi = 0;
+    let mut errors = 0;
+    for &op in alignment {
+        match op {
+            EDLIB_EDOP_MATCH => qi += 1,
+            EDLIB_EDOP_MISMATCH | EDLIB_EDOP_INSERT => {
+                if region.contains(&qi) {
+                    errors += 1;
+                }
+                qi += 1;
+            },
+            EDLIB_EDOP_DELETE if qi > region.start && qi < region.end => errors += 1,
+            _ => {},
+        }
+    }
+    errors
+}
+
 fn create_primer_match(result: Option<AlignmentResult>) -> PrimerMatch {
     match result {
         Some(r) => PrimerMatch {
             position: Some(r.position),
+            end: Some(r.end),
             errors: Some(r.edit_distance as usize),
             found: true,
+            alignment: Some(r.alignment),
         },
         None => PrimerMatch {
             position: None,
+            end: None,
             errors: None,
             found: false,
+            alignment: None,
         },
     }
 }
 
 
-fn analyze_read(
-    record: &FastqRecord,
-    primers: &HashMap<String, (String, String)>,
-    max_errors: i32,
-    min_distance: usize,
-) -> Option<ReadAnalysis> {
-    let seq = &record.seq;
-    let mut best_result = None;
-    let mut best_score = i32::MAX;
-
-    // 对引物对进行排序以确保一致的顺序
-    let mut primer_pairs: Vec<_> = primers.iter().collect();
-    primer_pairs.sort_by(|a, b| a.0.cmp(b.0));
-
-    for (i, (name1, (seq1, seq1_rc))) in primer_pairs.iter().enumerate() {
-        for (name2, (seq2, seq2_rc)) in primer_pairs[i+1..].iter() {
-            // 正向链检查
-            if let (Some(f), Some(r)) = (
-                align_sequence(seq1.as_bytes(), seq, max_errors),
-                align_sequence(seq2_rc.as_bytes(), seq, max_errors)
-            ) {
-                if f.position < r.position {
-                    let score = f.edit_distance + r.edit_distance;
-                    if score < best_score {
-                        best_score = score;
-                        best_result = Some((
-                            name1.to_string(),  // 使用 to_string() 而不是 clone()
-                            name2.to_string(),  // 使用 to_string() 而不是 clone()
-                            '+',
-                            create_primer_match(Some(f)),
-                            create_primer_match(Some(r)),
-                        ));
-                    }
+// 单个引物在读段上的比对结果：正向序列与反向互补序列各比对一次。
+// 启用锚定窗口时，*_internal 表示窗口内没有命中、只在读段内部其他位置找到
+struct PrimerHits {
+    forward: Option<AlignmentResult>,
+    reverse: Option<AlignmentResult>,
+    forward_internal: bool,
+    reverse_internal: bool,
+}
+
+impl PrimerHits {
+    fn forward(&self, allow_internal: bool) -> Option<&AlignmentResult> {
+        self.forward.as_ref().filter(|_| allow_internal || !self.forward_internal)
+    }
+
+    fn reverse(&self, allow_internal: bool) -> Option<&AlignmentResult> {
+        self.reverse.as_ref().filter(|_| allow_internal || !self.reverse_internal)
+    }
+
+    fn any(&self, allow_internal: bool) -> bool {
+        self.forward(allow_internal).is_some() || self.reverse(allow_internal).is_some()
+    }
+}
+
+// 引物对在读段上的一次命中（f 位于读段 5' 端，r 为反向互补后位于 3' 端）
+struct PairHit {
+    f_idx: usize,
+    r_idx: usize,
+    strand: char,
+    f_match: PrimerMatch,
+    r_match: PrimerMatch,
+    score: i32,
+}
+
+// 正向命中位于反向命中之前，或两者重叠但反向命中结束得更晚（二聚体中两个引物的比对常常重叠）
+fn is_ordered_pair(f: &AlignmentResult, r: &AlignmentResult) -> bool {
+    f.position < r.position || (f.position < r.end && f.end <= r.end)
+}
+
+// 以 i 为正向引物、j 为反向引物，从缓存的比对结果中检查正负两条链，返回得分最优的命中
+fn check_pair(hits: &[PrimerHits], i: usize, j: usize, allow_internal: bool) -> Option<PairHit> {
+    let mut best: Option<PairHit> = None;
+
+    // 正向链检查
+    if let (Some(f), Some(r)) = (hits[i].forward(allow_internal), hits[j].reverse(allow_internal)) {
+        if is_ordered_pair(f, r) {
+            best = Some(PairHit {
+                f_idx: i,
+                r_idx: j,
+                strand: '+',
+                score: f.edit_distance + r.edit_distance,
+                f_match: create_primer_match(Some(f.clone())),
+                r_match: create_primer_match(Some(r.clone())),
+            });
+        }
+    }
+
+    // 反向链检查
+    if let (Some(f), Some(r)) = (hits[j].forward(allow_internal), hits[i].reverse(allow_internal)) {
+        let score = f.edit_distance + r.edit_distance;
+        if is_ordered_pair(f, r) && best.as_ref().is_none_or(|b| score < b.score) {
+            best = Some(PairHit {
+                f_idx: j,
+                r_idx: i,
+                strand: '-',
+                score,
+                f_match: create_primer_match(Some(f.clone())),
+                r_match: create_primer_match(Some(r.clone())),
+            });
+        }
+    }
+
+    best
+}
+
+// 在命中表上选择得分最优的引物对，返回命中、扩增子名称、是否为非预期引物对，
+// 以及得分与所选引物对相同的其他引物对（得分相同时保留第一个遇到的引物对）
+fn select_pair(
+    hits: &[PrimerHits],
+    primer_set: &PrimerSet,
+    allow_internal: bool,
+) -> (Option<PairHit>, Option<String>, bool, Vec<CompetingPair>) {
+    let mut best_result: Option<PairHit> = None;
+    let mut amplicon = None;
+    let mut competing = Vec::new();
+
+    // 有面板时只评估声明的引物对
+    for amp in &primer_set.amplicons {
+        // 替补引物组合与主引物对属于同一个扩增子，取其中得分最好的组合（并列时取主引物对）
+        let hit = std::iter::once((amp.forward_idx, amp.reverse_idx))
+            .chain(amp.alternatives.iter().copied())
+            .filter_map(|(f, r)| check_pair(hits, f, r, allow_internal))
+            .min_by_key(|hit| hit.score);
+        if let Some(hit) = hit {
+            match best_result.as_ref().map(|b| hit.score.cmp(&b.score)) {
+                None | Some(std::cmp::Ordering::Less) => {
+                    best_result = Some(hit);
+                    amplicon = Some(amp.name.clone());
+                    competing.clear();
+                },
+                Some(std::cmp::Ordering::Equal) => competing.push(CompetingPair {
+                    amplicon: Some(amp.name.clone()),
+                    forward: amp.forward.clone(),
+                    reverse: amp.reverse.clone(),
+                }),
+                Some(std::cmp::Ordering::Greater) => {},
+            }
+        }
+    }
+    if best_result.is_some() {
+        return (best_result, amplicon, false, competing);
+    }
+
+    // 没有面板，或声明的引物对均未命中时，检查所有引物组合；有面板时这些组合记为非预期引物对。
+    // 只有至少命中一次的引物才可能组成引物对，primers 已按名称排序
+    let found: Vec<usize> = (0..hits.len()).filter(|&i| hits[i].any(allow_internal)).collect();
+
+    // check_pair 返回的命中中 i 始终是扩增子的正向引物
+    for (n, &i) in found.iter().enumerate() {
+        for &j in &found[n+1..] {
+            if let Some(hit) = check_pair(hits, i, j, allow_internal) {
+                match best_result.as_ref().map(|b| hit.score.cmp(&b.score)) {
+                    None | Some(std::cmp::Ordering::Less) => {
+                        best_result = Some(hit);
+                        competing.clear();
+                    },
+                    Some(std::cmp::Ordering::Equal) => competing.push(CompetingPair {
+                        amplicon: None,
+                        forward: primer_set.primers[i].name.clone(),
+                        reverse: primer_set.primers[j].name.clone(),
+                    }),
+                    Some(std::cmp::Ordering::Greater) => {},
                 }
             }
+        }
+    }
+    let unexpected_pair = !primer_set.amplicons.is_empty() && best_result.is_some();
+    (best_result, None, unexpected_pair, competing)
+}
 
-            // 反向链检查
-            if let (Some(f), Some(r)) = (
-                align_sequence(seq2.as_bytes(), seq, max_errors),
-                align_sequence(seq1_rc.as_bytes(), seq, max_errors)
-            ) {
-                if f.position < r.position {
-                    let score = f.edit_distance + r.edit_distance;
-                    if score < best_score {
-                        best_score = score;
-                        best_result = Some((
-                            name2.to_string(),  // 使用 to_string() 而不是 clone()
-                            name1.to_string(),  // 使用 to_string() 而不是 clone()
-                            '-',
-                            create_primer_match(Some(f)),
-                            create_primer_match(Some(r)),
-                        ));
-                    }
+// 由正向、反向命中在读段上的区间计算 (扩增子长度, 插入片段长度, 重叠长度)。
+// 反向命中可能落在正向命中内部，扩增子末端取两者中靠后的一个；引物不重叠时重叠长度为 0，重叠时插入片段长度为 0
+fn pair_geometry(forward: Range<usize>, reverse: Range<usize>) -> (usize, usize, usize) {
+    (
+        forward.end.max(reverse.end).saturating_sub(forward.start),
+        reverse.start.saturating_sub(forward.end),
+        forward.end.saturating_sub(reverse.start),
+    )
+}
+
+// 二聚体的插入片段长度阈值。声明了预期长度的扩增子取 --min-distance 与
+// (预期最小长度 - 两个引物长度) / 2 中的较小值，避免短扩增子的正常产物被判定为二聚体
+fn dimer_threshold(min_distance: usize, size_range: Option<(usize, usize)>, primer_len: usize) -> usize {
+    match size_range {
+        Some((min, _)) => min_distance.min(min.saturating_sub(primer_len) / 2),
+        None => min_distance,
+    }
+}
+
+// 按扩增子长度分类，只对声明了预期长度的扩增子分类；二聚体优先，其次按预期范围（闭区间）判断偏短或偏长
+fn classify_size(size_range: Option<(usize, usize)>, amplicon_length: Option<usize>, is_dimer: bool) -> Option<SizeClass> {
+    match (size_range, amplicon_length) {
+        (Some(_), _) if is_dimer => Some(SizeClass::Dimer),
+        (Some((min, _)), Some(len)) if len < min => Some(SizeClass::Short),
+        (Some((_, max)), Some(len)) if len > max => Some(SizeClass::Long),
+        (Some(_), Some(_)) => Some(SizeClass::Expected),
+        _ => None,
+    }
+}
+
+// 单个引物命中：(引物下标, 是否为反向互补命中, 比对结果)
+type SingleHit<'a> = (usize, bool, &'a AlignmentResult);
+
+// 没有找到引物对时，返回最优的单个引物命中。
+// 优先选择锚定窗口内的命中，其次是错误数最少的命中
+fn best_single_hit(hits: &[PrimerHits]) -> Option<SingleHit<'_>> {
+    // 排序键：(是否为内部命中, 错误数)
+    let mut best: Option<((bool, i32), SingleHit)> = None;
+    for (i, h) in hits.iter().enumerate() {
+        let candidates = [
+            (h.forward.as_ref(), h.forward_internal, false),
+            (h.reverse.as_ref(), h.reverse_internal, true),
+        ];
+        for (result, internal, is_reverse) in candidates {
+            if let Some(r) = result {
+                let key = (internal, r.edit_distance);
+                if best.as_ref().is_none_or(|(k, _)| key < *k) {
+                    best = Some((key, (i, is_reverse, r)));
                 }
             }
         }
     }
+    best.map(|(_, hit)| hit)
+}
 
+// 在 target[window] 范围内比对，返回的位置换算回整条读段的坐标
+fn align_window(query: &[u8], target: &[u8], window: Range<usize>, max_errors: i32) -> Option<AlignmentResult> {
+    let offset = window.start;
+    align_sequence(query, &target[window], max_errors).map(|mut r| {
+        r.position += offset;
+        r.end += offset;
+        r
+    })
+}
 
-    let default_match = PrimerMatch {
-        position: None,
-        errors: None,
-        found: false,
+fn analyze_read(
+    record: &FastqRecord,
+    primer_set: &PrimerSet,
+    options: &AnalysisOptions,
+) -> Option<ReadAnalysis> {
+    let seq = &record.seq;
+    let masked = mask_read(seq);
+    let primers = &primer_set.primers;
+
+    // k-mer 预筛选确定需要比对的目标；没有索引时比对全部引物
+    let mut targets = vec![primer_set.index.is_none(); primers.len() * 2];
+    if let Some(index) = &primer_set.index {
+        index.candidates(seq, &mut targets);
+    }
+
+    // 正向引物只在读段前 N 个碱基中查找，反向互补引物只在最后 N 个碱基中查找
+    let forward_window = options.forward_window.map(|n| 0..n.min(seq.len()));
+    let reverse_window = options.reverse_window.map(|n| seq.len() - n.min(seq.len())..seq.len());
+
+    // 每个引物及其反向互补序列只与读段比对一次，引物对的选择都基于这张表。
+    // 锚定窗口内没有命中时再比对整条读段，命中标记为内部命中
+    let mut prefilter = PrefilterCounts::default();
+    let mut three_prime_rejected = Vec::new();
+    let mut align_target = |target: usize, query: &str, max_errors: i32, window: &Option<Range<usize>>| {
+        if !targets[target] {
+            prefilter.skipped += 1;
+            if primer_set.index.as_ref().is_some_and(|index| index.verify)
+                && align_sequence(query.as_bytes(), &masked, max_errors).is_some()
+            {
+                prefilter.missed += 1;
+            }
+            return (None, false);
+        }
+
+        prefilter.candidates += 1;
+        let (result, internal) = match window {
+            Some(window) => match align_window(query.as_bytes(), &masked, window.clone(), max_errors) {
+                Some(r) => (Some(r), false),
+                None => (align_sequence(query.as_bytes(), &masked, max_errors), true),
+            },
+            None => (align_sequence(query.as_bytes(), &masked, max_errors), false),
+        };
+        if result.is_some() {
+            prefilter.confirmed += 1;
+        }
+
+        // 3' 端错误过多的命中无法延伸，视为未命中
+        let reverse = target % 2 == 1;
+        let result = result.filter(|r| {
+            let passed = !options.checks_three_prime()
+                || three_prime_errors(&r.alignment, query.len(), reverse, options.three_prime_length)
+                    <= options.three_prime_errors;
+            if !passed && !three_prime_rejected.contains(&(target / 2)) {
+                three_prime_rejected.push(target / 2);
+            }
+            passed
+        });
+        (result, internal)
     };
+    let hits: Vec<PrimerHits> = primers
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let (forward, forward_internal) = align_target(i * 2, &p.seq, p.max_errors, &forward_window);
+            let (reverse, reverse_internal) = align_target(i * 2 + 1, &p.rc_seq, p.max_errors, &reverse_window);
+            PrimerHits { forward, reverse, forward_internal, reverse_internal }
+        })
+        .collect();
+    let primer_hits: Vec<ReadPrimerHit> = hits
+        .iter()
+        .enumerate()
+        .filter_map(|(i, h)| {
+            let errors = [h.forward.as_ref(), h.reverse.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(|r| r.edit_distance as usize)
+                .min()?;
+            Some(ReadPrimerHit { primer: i, as_forward: h.forward.is_some(), as_reverse: h.reverse.is_some(), errors })
+        })
+        .collect();
+
+    // 先只使用锚定窗口内的命中；找不到引物对时再允许内部命中，命中即为内部引发
+    let (mut best_result, mut amplicon, mut unexpected_pair, mut competing_pairs) = select_pair(&hits, primer_set, false);
+    let mut internal_priming = false;
+    if best_result.is_none() && options.is_anchored() {
+        (best_result, amplicon, unexpected_pair, competing_pairs) = select_pair(&hits, primer_set, true);
+        internal_priming = best_result.is_some();
+    }
+
+    let default_match = create_primer_match(None);
 
     match best_result {
-        Some((f_name, r_name, strand, f_match, r_match)) => {
+        Some(PairHit { f_idx, r_idx, strand, f_match, r_match, .. }) => {
             let distance = match (f_match.position, r_match.position) {
                 (Some(f_pos), Some(r_pos)) => {
                     if r_pos > f_pos {
@@ -655,34 +2663,112 @@ fn analyze_read(
                 _ => None,
             };
 
-            let is_dimer = match distance {
-                Some(d) => d < min_distance,
-                None => false,
+            let (amplicon_length, insert_length, overlap) = match (f_match.position, f_match.end, r_match.position, r_match.end) {
+                (Some(f_pos), Some(f_end), Some(r_pos), Some(r_end)) => {
+                    let (amplicon_length, insert_length, overlap) = pair_geometry(f_pos..f_end, r_pos..r_end);
+                    (Some(amplicon_length), Some(insert_length), Some(overlap))
+                },
+                _ => (None, None, None),
+            };
+
+            let size_range = amplicon.as_ref()
+                .and_then(|name| primer_set.amplicons.iter().find(|a| &a.name == name))
+                .and_then(|a| a.size_range);
+            let primer_len = primers[f_idx].seq.len() + primers[r_idx].seq.len();
+            let threshold = dimer_threshold(options.min_distance, size_range, primer_len);
+
+            // 引物比对重叠或插入片段过短时判定为二聚体
+            let is_dimer = match (insert_length, overlap) {
+                (Some(insert), Some(overlap)) => overlap > 0 || insert < threshold,
+                _ => false,
+            };
+            let size_class = classify_size(size_range, amplicon_length, is_dimer);
+
+            let category = if is_dimer {
+                ReadCategory::PrimerDimer
+            } else if internal_priming {
+                ReadCategory::InternalPriming
+            } else if unexpected_pair {
+                ReadCategory::OffTargetPair
+            } else {
+                ReadCategory::OnTarget
             };
 
             Some(ReadAnalysis {
                 read_id: record.id.clone(),
                 length: record.seq.len(),
                 strand,
-                f_primer: f_name,
-                r_primer: r_name,
+                f_primer: primers[f_idx].name.clone(),
+                r_primer: primers[r_idx].name.clone(),
                 f_match,
                 r_match,
                 distance,
+                amplicon_length,
+                insert_length,
+                overlap,
                 is_dimer,
+                size_class,
+                amplicon,
+                unexpected_pair,
+                internal_priming,
+                category,
+                prefilter,
+                three_prime_rejected,
+                primer_hits,
+                ambiguous: !competing_pairs.is_empty(),
+                competing_pairs,
+            })
+        },
+        None => {
+            // 没有引物对时仍记录找到的单个引物及其位置（正向命中记在 F 列，反向互补命中记在 R 列）
+            let mut f_primer = String::from("-");
+            let mut r_primer = String::from("-");
+            let mut f_match = default_match.clone();
+            let mut r_match = default_match;
+            // 按命中的引物种类数分类：同一个引物在两个方向上命中仍是单引物扩增产物
+            let primer_count = hits.iter()
+                .filter(|h| h.forward.is_some() || h.reverse.is_some())
+                .count();
+            let category = match primer_count {
+                0 => ReadCategory::NoPrimer,
+                1 => ReadCategory::SinglePrimer,
+                _ => ReadCategory::Chimera,
+            };
+            if let Some((idx, is_reverse, result)) = best_single_hit(&hits) {
+                if is_reverse {
+                    r_primer = primers[idx].name.clone();
+                    r_match = create_primer_match(Some(result.clone()));
+                } else {
+                    f_primer = primers[idx].name.clone();
+                    f_match = create_primer_match(Some(result.clone()));
+                }
+            }
+
+            Some(ReadAnalysis {
+                read_id: record.id.clone(),
+                length: record.seq.len(),
+                strand: '?',
+                f_primer,
+                r_primer,
+                f_match,
+                r_match,
+                distance: None,
+                amplicon_length: None,
+                insert_length: None,
+                overlap: None,
+                is_dimer: false,
+                size_class: None,
+                amplicon: None,
+                unexpected_pair: false,
+                internal_priming: false,
+                category,
+                prefilter,
+                three_prime_rejected,
+                primer_hits,
+                ambiguous: false,
+                competing_pairs: Vec::new(),
             })
         },
-        None => Some(ReadAnalysis {
-            read_id: record.id.clone(),
-            length: record.seq.len(),
-            strand: '?',
-            f_primer: String::from("-"),
-            r_primer: String::from("-"),
-            f_match: default_match.clone(),
-            r_match: default_match,
-            distance: None,
-            is_dimer: false,
-        })
     }
 }
 
@@ -709,15 +2795,13 @@ impl ReadBatch {
 fn process_reads<R1: BufRead, R2: BufRead>(
     reader1: R1,
     reader2: Option<R2>,
-    primers: &HashMap<String, (String, String)>,
-    max_errors: i32,
-    min_distance: usize,
+    primer_set: &PrimerSet,
+    options: &AnalysisOptions,
     min_overlap: usize,
     max_mismatch_rate: f64,
-    outdir: &str,
-    sample: &str,
-    max_output: usize,
+    output: &OutputOptions,
 ) -> Result<()> {
+    let sample = output.sample.as_str();
     let mut parser1 = FastqParser::new(reader1);
     let mut parser2 = reader2.map(|r| FastqParser::new(r));
     
@@ -726,13 +2810,30 @@ fn process_reads<R1: BufRead, R2: BufRead>(
     let mut record2 = FastqRecord::new();
 
     // 创建结果写入器
-    let result_file = PathBuf::from(outdir)
+    let result_file = PathBuf::from(&output.outdir)
         .join(format!("{}_primer_analysis.txt.gz", sample));
     let mut writer = AnalysisWriter::new(
         result_file.to_str().unwrap(),
         sample,
-        max_output
+        output.max_output,
+        output.report_preview,
     )?;
+    if let Some(mode) = output.trim {
+        writer.trimmed_output = Some(TrimmedOutput::new(Path::new(&output.outdir), sample, mode)?);
+    }
+    if output.demux {
+        writer.demux_output = Some(DemuxOutput::new(Path::new(&output.outdir), sample));
+    }
+    writer.ambiguous_policy = output.ambiguous;
+    writer.dropout_threshold = output.dropout_threshold;
+    writer.amplicon_primers = primer_set.amplicons
+        .iter()
+        .map(|a| (a.name.clone(), (a.forward.clone(), a.reverse.clone())))
+        .collect();
+    writer.stats.mismatch_profiles = primer_set.primers
+        .iter()
+        .map(|p| (p.name.clone(), MismatchProfile::new(p)))
+        .collect();
 
     // 创建通道用于传输分析结果
     let (tx, rx) = mpsc::channel();
@@ -740,8 +2841,8 @@ fn process_reads<R1: BufRead, R2: BufRead>(
     // 启动写入线程
     let writer_thread = thread::spawn(move || {
         let mut writer = writer;
-        for analysis in rx {
-            if let Err(e) = writer.process(&analysis) {
+        for (analysis, record) in rx {
+            if let Err(e) = writer.process(&analysis, &record) {
                 eprintln!("写入结果时发生错误: {}", e);
             }
         }
@@ -756,19 +2857,24 @@ fn process_reads<R1: BufRead, R2: BufRead>(
 
     // 处理批次的闭包
     let process_batch = |batch: ReadBatch, 
-                        primers: &HashMap<String, (String, String)>,
-                        tx: &mpsc::Sender<ReadAnalysis>| {
-        batch.records.par_iter().for_each(|record| {
-            if let Some(analysis) = if let Some(ref merged) = record.merged {
-                analyze_read(merged, primers, max_errors, min_distance)
-            } else {
-                analyze_read(&record.r1, primers, max_errors, min_distance)
-            } {
-                if let Err(e) = tx.send(analysis) {
-                    eprintln!("发送分析结果时发生错误: {}", e);
-                }
+                        primer_set: &PrimerSet,
+                        tx: &mpsc::Sender<(ReadAnalysis, FastqRecord)>| {
+        // 并行分析后按输入顺序收集，保证输出顺序与输入一致、重复运行结果相同
+        let results: Vec<(ReadAnalysis, FastqRecord)> = batch.records
+            .into_par_iter()
+            .filter_map(|record| {
+                // 双端数据使用合并后的序列，分析结果和对应的序列一起交给写入线程
+                let read = record.merged.unwrap_or(record.r1);
+                analyze_read(&read, primer_set, options).map(|analysis| (analysis, read))
+            })
+            .collect();
+
+        for result in results {
+            if let Err(e) = tx.send(result) {
+                eprintln!("发送分析结果时发生错误: {}", e);
+                break;
             }
-        });
+        }
     };
 
     loop {
@@ -811,7 +2917,7 @@ fn process_reads<R1: BufRead, R2: BufRead>(
                 // 如果批次已满，进行并行处理
                 if current_batch.is_full() {
                     let batch = std::mem::replace(&mut current_batch, ReadBatch::new());
-                    process_batch(batch, primers, &tx);
+                    process_batch(batch, primer_set, &tx);
                 }
             },
             Ok(false) => break,
@@ -821,18 +2927,21 @@ fn process_reads<R1: BufRead, R2: BufRead>(
 
     // 处理最后一个批次
     if !current_batch.records.is_empty() {
-        process_batch(current_batch, primers, &tx);
+        process_batch(current_batch, primer_set, &tx);
     }
 
     // 关闭发送端，让接收线程知道没有更多数据
     drop(tx);
 
     // 等待写入线程完成并获取writer
-    let mut writer = writer_thread.join().unwrap();
+    let writer = writer_thread.join().unwrap();
 
     // 保存统计信息
-    let statistics = writer.get_statistics();
+    let statistics = writer.get_statistics(primer_set, options);
     writer.save_statistics(&statistics)?;
+    writer.save_mismatch_profile(&statistics)?;
+    writer.save_length_histogram(&statistics)?;
+    writer.save_report(&statistics, primer_set)?;
 
     // 打印统计信息
     println!("总处理序列数: {}", record_count);
@@ -846,14 +2955,108 @@ fn process_reads<R1: BufRead, R2: BufRead>(
     println!("二聚体数量: {} ({:.2}%)", 
              statistics.dimer_count, 
              statistics.dimer_rate);
+    if !primer_set.amplicons.is_empty() {
+        println!("非预期引物对数量: {} ({:.2}%)",
+                 statistics.unexpected_pair_count,
+                 statistics.unexpected_pair_rate);
+    }
+    if options.is_anchored() {
+        println!("内部引发数量: {} ({:.2}%)",
+                 statistics.internal_priming_count,
+                 statistics.internal_priming_rate);
+    }
+    println!("只找到单个引物的读数: {} ({:.2}%)",
+             statistics.single_primer_count,
+             statistics.single_primer_rate);
+    println!("引物对得分并列的读数: {} ({:.2}%，计数方式: {})",
+             statistics.ambiguous_count,
+             statistics.ambiguous_rate,
+             statistics.ambiguous_policy);
+
+    println!("\n读段分类统计:");
+    for category in &statistics.categories {
+        println!("{}: {} ({:.2}%)", category.category, category.count, category.percentage);
+    }
 
     println!("\n引物对使用统计:");
     for pair in &statistics.primer_pairs {
-        println!("{} - {}: {} ({:.2}%)",
+        let label = match (&pair.amplicon, pair.unexpected) {
+            (Some(name), _) => format!("[{}] ", name),
+            (None, true) => "[非预期] ".to_string(),
+            (None, false) => String::new(),
+        };
+        println!("{}{} - {}: {} ({:.2}%)",
+                label,
                 pair.forward_primer,
                 pair.reverse_primer,
-                pair.count,
+                format_count(pair.assigned),
                 pair.percentage);
+        if let Some(size_classes) = &pair.size_classes {
+            let summary: Vec<String> = SizeClass::ALL
+                .iter()
+                .map(|c| format!("{} {}", c.as_str(), size_classes.get(c.as_str()).copied().unwrap_or(0)))
+                .collect();
+            println!("    产物大小: {}", summary.join(", "));
+        }
+    }
+
+    if let Some(uniformity) = &statistics.uniformity {
+        println!("\n扩增子覆盖均一性 ({} 个扩增子，基于{}):",
+                 uniformity.amplicons,
+                 if uniformity.basis == "panel" { "面板" } else { "观察到的引物对" });
+        println!("平均读数: {:.2}，中位读数: {:.2}，变异系数: {:.3}",
+                 uniformity.mean_reads,
+                 uniformity.median_reads,
+                 uniformity.cv);
+        println!(">= 0.2x 均值: {:.2}%，>= 0.5x 均值: {:.2}%，Fold-80: {}",
+                 uniformity.within_0_2x,
+                 uniformity.within_0_5x,
+                 uniformity.fold_80_penalty.map_or("-".to_string(), |f| format!("{:.2}", f)));
+        if uniformity.dropouts.is_empty() {
+            println!("脱落扩增子 (< {}x 均值): 无", uniformity.dropout_threshold);
+        } else {
+            println!("脱落扩增子 (< {}x 均值): {}", uniformity.dropout_threshold, uniformity.dropouts.join(", "));
+        }
+    }
+
+    if let Some(trimming) = &statistics.trimming {
+        println!("\n去除引物的读数: {}，未去除引物的读数: {}{}",
+                 trimming.trimmed_reads,
+                 trimming.untrimmed_reads,
+                 if trimming.untrimmed_written { "" } else { "（已丢弃）" });
+    }
+
+    if let Some(files) = &statistics.demultiplex {
+        println!("\n按引物对拆分输出 {} 个文件", files.len());
+    }
+
+    if let Some(three_prime) = &statistics.three_prime {
+        println!("\n3' 端错误过多被丢弃命中的读数: {}", three_prime.rejected_reads);
+        for primer in three_prime.primers.iter().filter(|p| p.rejected_reads > 0) {
+            println!("{}: {}", primer.primer, primer.rejected_reads);
+        }
+    }
+
+    println!("\n引物命中统计 (读段数，正向 / 反向，+链 / -链 / 未配对，平均错误数，错误数分布):");
+    for hits in &statistics.primer_hits {
+        let errors: Vec<String> = hits.errors.iter().enumerate().map(|(e, n)| format!("{}:{}", e, n)).collect();
+        println!("{}: {}，{} / {}，{} / {} / {}，{:.2}，{}",
+                 hits.primer,
+                 hits.reads,
+                 hits.as_forward,
+                 hits.as_reverse,
+                 hits.plus_strand,
+                 hits.minus_strand,
+                 hits.unpaired,
+                 hits.mean_errors,
+                 errors.join(" "));
+    }
+
+    if !statistics.single_primers.is_empty() {
+        println!("\n单引物命中统计 (仅正向 / 仅反向):");
+        for single in &statistics.single_primers {
+            println!("{}: {} / {}", single.primer, single.forward_only, single.reverse_only);
+        }
     }
 
     Ok(())
@@ -861,16 +3064,45 @@ fn process_reads<R1: BufRead, R2: BufRead>(
 
 // main 函数需要相应修改
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let args = match cli.command {
+        Some(Command::Rebalance(args)) => return rebalance::run(args),
+        Some(Command::CheckPrimers(args)) => return check_primers::run(args),
+        None => cli.args.expect("未给出子命令时 clap 会检查分析参数"),
+    };
+
     let start_time = Instant::now();
-    let args = Args::parse();
     
     // 创建输出目录
     std::fs::create_dir_all(&args.outdir)?;
     
     println!("正在加载引物文件...");
-    let primers = load_primers(&args.primers)
+    let primer_options = PrimerOptions {
+        format: args.primer_format,
+        reference: args.reference.clone(),
+        max_errors: args.max_errors,
+        max_error_rate: args.max_error_rate,
+        seed_length: args.seed_length,
+        verify_prefilter: args.verify_prefilter,
+        allow_collisions: args.allow_primer_collisions,
+        size_tolerance: args.size_tolerance,
+    };
+    let mut primer_set = load_primers(&args.primers, &primer_options)
         .context("加载引物文件失败")?;
-    println!("成功加载 {} 个引物", primers.len());
+    println!("成功加载 {} 个引物", primer_set.primers.len());
+    if !primer_set.amplicons.is_empty() {
+        println!("引物方案中声明了 {} 个扩增子", primer_set.amplicons.len());
+    }
+
+    if let Some(panel) = args.panel.as_ref() {
+        println!("正在加载扩增子面板文件...");
+        if !primer_set.amplicons.is_empty() {
+            println!("使用面板文件中的扩增子代替引物方案中的扩增子");
+        }
+        primer_set.amplicons = load_panel(panel, &primer_set.primers, args.size_tolerance)
+            .context("加载扩增子面板文件失败")?;
+        println!("成功加载 {} 个扩增子", primer_set.amplicons.len());
+    }
 
     println!("正在读取FASTQ文件...");
     let file1 = File::open(&args.input)
@@ -903,22 +3135,408 @@ fn main() -> Result<()> {
         None
     };
 
+    let options = AnalysisOptions {
+        min_distance: args.min_distance,
+        forward_window: args.forward_window,
+        reverse_window: args.reverse_window,
+        three_prime_length: args.three_prime_length,
+        three_prime_errors: args.three_prime_errors,
+    };
+
+    let output = OutputOptions {
+        outdir: args.outdir.clone(),
+        sample: args.sample.clone(),
+        max_output: args.max_output,
+        trim: args.trim.then_some(args.untrimmed),
+        demux: args.demux,
+        ambiguous: args.ambiguous,
+        dropout_threshold: args.dropout_threshold,
+        report_preview: args.report_preview,
+    };
+
     println!("开始分析序列...");
     process_reads(
         reader1,
         reader2,
-        &primers,
-        args.max_errors,
-        args.min_distance,
+        &primer_set,
+        &options,
         args.min_overlap,
         args.max_mismatch_rate,
-        &args.outdir,
-        &args.sample,
-        args.max_output,  // 传递 max_output 参数
+        &output,
     ).context("序列分析过程中发生错误")?;
     
     let total_time = start_time.elapsed();
     println!("分析完成! 总运行时间: {:.2}s", total_time.as_secs_f64());
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn primer(name: &str, seq: &str, max_errors: i32) -> Primer {
+        Primer {
+            name: name.to_string(),
+            seq: seq.to_string(),
+            rc_seq: String::from_utf8_lossy(&revcomp(seq.as_bytes())).into_owned(),
+            max_errors,
+        }
+    }
+
+    fn candidate_targets(index: &KmerIndex, primers: &[Primer], read: &str) -> Vec<bool> {
+        let mut targets = vec![false; primers.len() * 2];
+        index.candidates(read.as_bytes(), &mut targets);
+        targets
+    }
+
+    #[test]
+    fn pair_geometry_normal_pair() {
+        // 正向引物 0..20，反向引物 130..150：扩增子 150，插入片段 110，无重叠
+        assert_eq!(pair_geometry(0..20, 130..150), (150, 110, 0));
+    }
+
+    #[test]
+    fn pair_geometry_overlapping_pair() {
+        // 引物比对区域重叠 6 个碱基
+        assert_eq!(pair_geometry(5..25, 19..39), (34, 0, 6));
+    }
+
+    #[test]
+    fn pair_geometry_nested_hit() {
+        // 反向命中落在正向命中内部，扩增子末端取正向命中的末端
+        assert_eq!(pair_geometry(10..40, 15..30), (30, 0, 25));
+    }
+
+    #[test]
+    fn dimer_threshold_uses_expected_size() {
+        // 没有预期长度时使用 --min-distance
+        assert_eq!(dimer_threshold(80, None, 40), 80);
+        // 预期最小长度 120、引物共 40 bp：(120 - 40) / 2 = 40 < 80
+        assert_eq!(dimer_threshold(80, Some((120, 150)), 40), 40);
+        // 长扩增子仍使用 --min-distance
+        assert_eq!(dimer_threshold(80, Some((400, 440)), 40), 80);
+        // 预期长度小于引物长度之和时阈值为 0
+        assert_eq!(dimer_threshold(80, Some((30, 50)), 40), 0);
+    }
+
+    #[test]
+    fn classify_size_boundaries() {
+        let range = Some((135, 165));
+        let cases = [
+            (Some(134), false, Some(SizeClass::Short)),
+            (Some(135), false, Some(SizeClass::Expected)),
+            (Some(150), false, Some(SizeClass::Expected)),
+            (Some(165), false, Some(SizeClass::Expected)),
+            (Some(166), false, Some(SizeClass::Long)),
+            // 二聚体优先于长度判断
+            (Some(150), true, Some(SizeClass::Dimer)),
+            (Some(40), true, Some(SizeClass::Dimer)),
+            (None, false, None),
+        ];
+        for (length, is_dimer, expected) in cases {
+            assert_eq!(classify_size(range, length, is_dimer), expected, "length {:?}, dimer {}", length, is_dimer);
+        }
+        // 没有声明预期长度时不分类
+        assert_eq!(classify_size(None, Some(150), true), None);
+    }
+
+    #[test]
+    fn kmer_index_keeps_hit_with_spread_substitutions() {
+        // 20 bp、种子长度 5 得到 4 个种子，允许 3 个错误时恰好满足鸽巢原理
+        let primers = vec![primer("P1", "GATTACAGGCATCCAGTTGA", 3)];
+        let index = KmerIndex::new(&primers, 5, false);
+        assert!(index.always.is_empty());
+
+        // 前三个种子各有一个替换，只剩最后一个种子完整
+        let read = "TTTTCATTAGAGGCTTCCAGTTGATTTT";
+        assert_eq!(candidate_targets(&index, &primers, read), vec![true, false]);
+        // 四个种子都有替换时不再是候选
+        let read = "TTTTCATTAGAGGCTTCCAGTAGATTTT";
+        assert_eq!(candidate_targets(&index, &primers, read), vec![false, false]);
+        // 反向互补命中只标记反向目标
+        let rc = String::from_utf8_lossy(&revcomp(b"GATTACAGGCATCCAGTTGA")).into_owned();
+        assert_eq!(candidate_targets(&index, &primers, &rc), vec![false, true]);
+    }
+
+    #[test]
+    fn kmer_index_falls_back_when_too_few_seeds() {
+        // 4 个种子不足以覆盖 4 个错误，正向和反向互补都需要每条读段比对
+        let primers = vec![primer("P1", "GATTACAGGCATCCAGTTGA", 4)];
+        let index = KmerIndex::new(&primers, 5, false);
+        assert_eq!(index.always, vec![0, 1]);
+        assert!(index.seeds.is_empty());
+        assert_eq!(candidate_targets(&index, &primers, "CCCCCCCCCC"), vec![true, true]);
+        assert_eq!(index.unindexed_primers(&primers), vec!["P1".to_string()]);
+    }
+
+    #[test]
+    fn kmer_index_skips_iupac_bases() {
+        // 种子跳过简并碱基 R：GATTA、CAGGC、TCCAG
+        let seeds = KmerIndex::pick_seeds(b"GATTACAGGCRTCCAGTTGA", 5);
+        let expected: Vec<u64> = ["GATTA", "CAGGC", "TCCAG"]
+            .iter()
+            .map(|k| encode_kmer(k.as_bytes()).unwrap())
+            .collect();
+        assert_eq!(seeds, expected);
+
+        // 简并位置为任意碱基的读段都是候选
+        let primers = vec![primer("P1", "GATTACAGGCRTCCAGTTGA", 1)];
+        let index = KmerIndex::new(&primers, 5, false);
+        assert!(index.always.is_empty());
+        for read in ["GATTACAGGCATCCAGTTGA", "GATTACAGGCGTCCAGTTGA"] {
+            assert!(candidate_targets(&index, &primers, read)[0], "{}", read);
+        }
+
+        // 简并碱基过多、可用种子不足时回退为每条读段比对
+        let primers = vec![primer("P2", "ACGTRACGTYACGTA", 1)];
+        let index = KmerIndex::new(&primers, 5, false);
+        assert_eq!(index.always, vec![0, 1]);
+    }
+
+    const M: u8 = EDLIB_EDOP_MATCH;
+    const X: u8 = EDLIB_EDOP_MISMATCH;
+    const I: u8 = EDLIB_EDOP_INSERT;
+    const D: u8 = EDLIB_EDOP_DELETE;
+
+    // 在 10 个匹配中的第 at 个位置插入操作 op（I/X 占用一个引物碱基，D 不占用）
+    fn cigar_with(op: u8, at: usize) -> Vec<u8> {
+        let mut alignment = vec![M; 10];
+        if op == D {
+            alignment.insert(at, D);
+        } else {
+            alignment[at] = op;
+        }
+        alignment
+    }
+
+    #[test]
+    fn three_prime_errors_forward() {
+        // 正向序列的 3' 端为引物第 7..10 个碱基
+        let cases = [
+            (X, 8, 1),
+            (X, 2, 0),
+            (I, 9, 1),
+            (I, 1, 0),
+            // 读段多出的碱基位于第 7、8 个引物碱基之间
+            (D, 8, 1),
+            // 位于区域边界外侧（区域第一个碱基之前、最后一个碱基之后）时不计入
+            (D, 7, 0),
+            (D, 10, 0),
+        ];
+        for (op, at, expected) in cases {
+            assert_eq!(three_prime_errors(&cigar_with(op, at), 10, false, 3), expected, "op {} at {}", op, at);
+        }
+    }
+
+    #[test]
+    fn three_prime_errors_reverse() {
+        // 反向互补序列的 3' 端在比对开头，即第 0..3 个碱基
+        let cases = [
+            (X, 1, 1),
+            (X, 7, 0),
+            (I, 0, 1),
+            (I, 5, 0),
+            (D, 2, 1),
+            (D, 0, 0),
+            (D, 3, 0),
+        ];
+        for (op, at, expected) in cases {
+            assert_eq!(three_prime_errors(&cigar_with(op, at), 10, true, 3), expected, "op {} at {}", op, at);
+        }
+    }
+
+    #[test]
+    fn three_prime_errors_disabled_or_clamped() {
+        let alignment = vec![X; 10];
+        assert_eq!(three_prime_errors(&alignment, 10, false, 0), 0);
+        assert_eq!(three_prime_errors(&alignment, 10, true, 0), 0);
+        // 区域长度超过引物长度时按整条引物统计
+        assert_eq!(three_prime_errors(&alignment, 10, false, 20), 10);
+        assert_eq!(three_prime_errors(&alignment, 10, true, 20), 10);
+    }
+
+    #[test]
+    fn mismatch_profile_reverse_hit_uses_primer_orientation() {
+        // 引物 ACGTTG 的反向互补为 CAACGT；读段命中 CAGCT：
+        // 第 3 个比对碱基 A -> G 错配，第 5 个碱基 G 在读段中缺失
+        let p = primer("P1", "ACGTTG", 0);
+        assert_eq!(p.rc_seq, "CAACGT");
+        let mut profile = MismatchProfile::new(&p);
+        profile.add(&[M, M, X, M, I, M], b"CAGCT", true);
+
+        assert_eq!(profile.hits, 1);
+        // 错配落在引物第 4 个碱基（T），观测碱基 G 取互补后为 C
+        let mismatch = &profile.positions[3];
+        assert_eq!(profile.seq[3], b'T');
+        assert_eq!(mismatch.mismatches, 1);
+        assert_eq!(mismatch.substitutions, BTreeMap::from([('C', 1)]));
+        // 缺失落在引物第 2 个碱基（C）
+        assert_eq!(profile.positions[1].insertions, 1);
+        assert_eq!(profile.positions[1].matches, 0);
+        for pos in [0, 2, 4, 5] {
+            assert_eq!(profile.positions[pos].matches, 1, "position {}", pos);
+            assert_eq!(profile.positions[pos].mismatches, 0, "position {}", pos);
+        }
+
+        // 同样的错配出现在正向命中时，位置和观测碱基不做转换
+        let mut profile = MismatchProfile::new(&p);
+        profile.add(&[M, M, X, M, M, M], b"ACTTTG", false);
+        assert_eq!(profile.positions[2].substitutions, BTreeMap::from([('T', 1)]));
+    }
+
+    fn pair_stat(amplicon: Option<&str>, forward: &str, reverse: &str, assigned: f64) -> PrimerPairStat {
+        PrimerPairStat {
+            amplicon: amplicon.map(str::to_string),
+            forward_primer: forward.to_string(),
+            reverse_primer: reverse.to_string(),
+            size_range: None,
+            pool: None,
+            unexpected: false,
+            count: assigned as usize,
+            ambiguous: 0,
+            assigned,
+            percentage: 0.0,
+            categories: BTreeMap::new(),
+            size_classes: None,
+            distance_lengths: LengthHistogram::default().summary(),
+            read_lengths: LengthHistogram::default().summary(),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    struct UniformityCase {
+        counts: &'static [f64],
+        mean: f64,
+        median: f64,
+        cv: f64,
+        fold_80: Option<f64>,
+        within_0_2x: f64,
+        dropouts: &'static [&'static str],
+    }
+
+    #[test]
+    fn coverage_uniformity_metrics() {
+        let cases = [
+            // 完全均一
+            UniformityCase {
+                counts: &[100.0, 100.0, 100.0, 100.0],
+                mean: 100.0,
+                median: 100.0,
+                cv: 0.0,
+                fold_80: Some(1.0),
+                within_0_2x: 100.0,
+                dropouts: &[],
+            },
+            // 均值 200，第 20 百分位数 10 + (50 - 10) * 0.8 = 42
+            UniformityCase {
+                counts: &[10.0, 50.0, 100.0, 200.0, 640.0],
+                mean: 200.0,
+                median: 100.0,
+                cv: 52440f64.sqrt() / 200.0,
+                fold_80: Some(200.0 / 42.0),
+                within_0_2x: 80.0,
+                dropouts: &["A1"],
+            },
+            // 偶数个扩增子，一个没有读段：第 20 百分位数 0 + (90 - 0) * 0.6 = 54
+            UniformityCase {
+                counts: &[0.0, 90.0, 90.0, 120.0],
+                mean: 75.0,
+                median: 90.0,
+                cv: 0.6,
+                fold_80: Some(75.0 / 54.0),
+                within_0_2x: 75.0,
+                dropouts: &["A1"],
+            },
+            // 所有扩增子都没有读段：Fold-80 无法计算，全部脱落
+            UniformityCase {
+                counts: &[0.0, 0.0, 0.0],
+                mean: 0.0,
+                median: 0.0,
+                cv: 0.0,
+                fold_80: None,
+                within_0_2x: 0.0,
+                dropouts: &["A1", "A2", "A3"],
+            },
+        ];
+        for case in cases {
+            let pairs: Vec<PrimerPairStat> = case.counts
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| pair_stat(Some(&format!("A{}", i + 1)), "F", "R", c))
+                .collect();
+            let stat = coverage_uniformity(&pairs, true, 0.2).unwrap();
+            assert_eq!(stat.basis, "panel");
+            assert_eq!(stat.amplicons, case.counts.len());
+            assert_close(stat.mean_reads, case.mean);
+            assert_close(stat.median_reads, case.median);
+            assert_close(stat.cv, case.cv);
+            match (stat.fold_80_penalty, case.fold_80) {
+                (Some(actual), Some(expected)) => assert_close(actual, expected),
+                (actual, expected) => assert_eq!(actual, expected, "{:?}", case.counts),
+            }
+            assert_close(stat.within_0_2x, case.within_0_2x);
+            assert_eq!(stat.dropouts, case.dropouts, "{:?}", case.counts);
+        }
+    }
+
+    #[test]
+    fn coverage_uniformity_basis() {
+        let pairs = vec![
+            pair_stat(Some("A1"), "F1", "R1", 100.0),
+            pair_stat(None, "F2", "R2", 10.0),
+            pair_stat(None, "-", "-", 500.0),
+        ];
+        // 有面板时只统计面板声明的扩增子
+        let stat = coverage_uniformity(&pairs, true, 0.2).unwrap();
+        assert_eq!(stat.amplicons, 1);
+        // 没有面板时统计所有观察到的引物对，跳过未找到引物对的读段
+        let stat = coverage_uniformity(&pairs, false, 0.2).unwrap();
+        assert_eq!(stat.basis, "observed");
+        assert_eq!(stat.amplicons, 2);
+        assert_eq!(stat.dropouts, vec!["F2-R2".to_string()]);
+        assert!(coverage_uniformity(&pairs[2..], false, 0.2).is_none());
+    }
+
+    #[test]
+    fn length_histogram_median() {
+        let cases: [(&[usize], Option<f64>); 6] = [
+            (&[], None),
+            (&[150], Some(150.0)),
+            // 偶数个读段取中间两个长度的平均值
+            (&[100, 150], Some(125.0)),
+            (&[100, 101, 150, 150], Some(125.5)),
+            // 中间两个读段长度相同
+            (&[100, 150, 150, 200], Some(150.0)),
+            (&[300, 100, 100, 200, 150], Some(150.0)),
+        ];
+        for (lengths, expected) in cases {
+            let mut histogram = LengthHistogram::default();
+            for &length in lengths {
+                histogram.add(length);
+            }
+            let summary = histogram.summary();
+            assert_eq!(summary.count, lengths.len());
+            assert_eq!(summary.median, expected, "{:?}", lengths);
+        }
+    }
+
+    #[test]
+    fn length_histogram_summary() {
+        let mut histogram = LengthHistogram::default();
+        for length in [100, 100, 200, 200, 300] {
+            histogram.add(length);
+        }
+        let summary = histogram.summary();
+        assert_close(summary.mean, 180.0);
+        // 读段数相同时众数取较短的长度
+        assert_eq!(summary.mode, Some(100));
+        assert_eq!((summary.min, summary.max), (Some(100), Some(300)));
+
+        let summary = LengthHistogram::default().summary();
+        assert_eq!(summary.mean, 0.0);
+        assert_eq!((summary.mode, summary.min, summary.max), (None, None, None));
+    }
+}
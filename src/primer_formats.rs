@@ -0,0 +1,451 @@
+// 引物文件格式：TSV（name\tsequence[\tmax_errors]）、FASTA、带表头的 CSV（订购平台导出）
+// 以及 ARTIC 风格的 .primer.bed 引物方案。BED 方案按 _LEFT/_RIGHT 后缀配对，同时给出扩增子及其引物池
+use anyhow::{Context, Result};
+use bio::alphabets::dna::revcomp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrimerFormat {
+    Auto,
+    Tsv,
+    Fasta,
+    Csv,
+    Bed,
+}
+
+impl PrimerFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PrimerFormat::Auto => "auto",
+            PrimerFormat::Tsv => "tsv",
+            PrimerFormat::Fasta => "fasta",
+            PrimerFormat::Csv => "csv",
+            PrimerFormat::Bed => "bed",
+        }
+    }
+}
+
+// 引物文件中的一条引物，max_errors 为文件中为该引物单独指定的最大错配数
+pub struct PrimerRecord {
+    pub line_no: usize,
+    pub name: String,
+    pub seq: String,
+    pub max_errors: Option<i32>,
+}
+
+// BED 引物方案中由 _LEFT/_RIGHT 引物组成的扩增子。forward/reverse 为主引物，alternatives 为替补引物
+// 参与的其他组合；size 为各组合含引物的产物长度范围 (最小, 最大)
+pub struct SchemeAmplicon {
+    pub name: String,
+    pub forward: String,
+    pub reverse: String,
+    pub alternatives: Vec<(String, String)>,
+    pub size: (usize, usize),
+    pub pool: Option<String>,
+}
+
+pub fn read_primer_file(
+    path: &str,
+    format: PrimerFormat,
+    reference: Option<&str>,
+) -> Result<(Vec<PrimerRecord>, Vec<SchemeAmplicon>)> {
+    let format = match format {
+        PrimerFormat::Auto => {
+            let detected = detect_format(path)?;
+            println!("引物文件格式: {}", detected.as_str());
+            detected
+        },
+        format => format,
+    };
+    let lines = read_lines(path)?;
+
+    match format {
+        PrimerFormat::Fasta => Ok((read_fasta(&lines)?, Vec::new())),
+        PrimerFormat::Csv => Ok((read_csv(&lines)?, Vec::new())),
+        PrimerFormat::Bed => read_bed(&lines, reference),
+        _ => Ok((read_tsv(&lines)?, Vec::new())),
+    }
+}
+
+// 按扩展名（忽略 .gz 后缀）判断格式，无法判断时以 > 开头的文件视为 FASTA，其他视为 TSV
+fn detect_format(path: &str) -> Result<PrimerFormat> {
+    let path_lower = path.to_lowercase();
+    let extension = Path::new(path_lower.strip_suffix(".gz").unwrap_or(&path_lower))
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "fa" | "fasta" | "fna" | "fas" => return Ok(PrimerFormat::Fasta),
+        "csv" => return Ok(PrimerFormat::Csv),
+        "bed" => return Ok(PrimerFormat::Bed),
+        _ => {},
+    }
+
+    let first_line = read_lines(path)?
+        .into_iter()
+        .find(|l| !l.trim().is_empty() && !l.starts_with('#'));
+    Ok(match first_line {
+        Some(line) if line.trim_start_matches('\u{feff}').starts_with('>') => PrimerFormat::Fasta,
+        _ => PrimerFormat::Tsv,
+    })
+}
+
+fn read_lines(path: &str) -> Result<Vec<String>> {
+    let file = File::open(path).with_context(|| format!("无法打开文件: {}", path))?;
+    let reader: Box<dyn BufRead> = if path.ends_with(".gz") {
+        Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    reader.lines()
+        .map(|line| line.context("无法读取文件行，可能存在编码问题"))
+        .collect()
+}
+
+fn read_tsv(lines: &[String]) -> Result<Vec<PrimerRecord>> {
+    let mut records = Vec::new();
+    for (line_no, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 2 {
+            eprintln!("警告: 跳过格式不正确的行: {}", line);
+            continue;
+        }
+        // 第三列为该引物的最大错配数
+        let max_errors = match parts.get(2).map(|p| p.trim()) {
+            Some(s) if !s.is_empty() && s != "-" => Some(s.parse::<i32>()
+                .ok()
+                .filter(|k| *k >= 0)
+                .ok_or_else(|| anyhow::anyhow!("引物文件第 {} 行最大错配数无效: {}", line_no + 1, s))?),
+            _ => None,
+        };
+        records.push(PrimerRecord {
+            line_no: line_no + 1,
+            name: parts[0].trim_start_matches('\u{feff}').trim().to_string(),
+            seq: parts[1].trim().to_string(),
+            max_errors,
+        });
+    }
+    Ok(records)
+}
+
+// FASTA 记录的名称取 > 之后的第一个词，序列可以分多行；行号为记录标题所在行
+fn read_fasta(lines: &[String]) -> Result<Vec<PrimerRecord>> {
+    let mut records: Vec<PrimerRecord> = Vec::new();
+    for (line_no, line) in lines.iter().enumerate() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            let name = header.split_whitespace().next().unwrap_or("");
+            if name.is_empty() {
+                anyhow::bail!("FASTA 文件第 {} 行缺少序列名称", line_no + 1);
+            }
+            records.push(PrimerRecord {
+                line_no: line_no + 1,
+                name: name.to_string(),
+                seq: String::new(),
+                max_errors: None,
+            });
+        } else {
+            match records.last_mut() {
+                Some(record) => record.seq.extend(line.split_whitespace()),
+                None => anyhow::bail!("FASTA 文件第 {} 行在第一个 > 标题之前出现序列", line_no + 1),
+            }
+        }
+    }
+    Ok(records)
+}
+
+// 第一行为表头。名称列和序列列优先取完全匹配的表头（如 name、sequence），否则取包含关键字的表头
+// （名称列为 name，其次 primer/oligo/id；序列列为 seq），序列列不会与名称列相同。
+// 可选的 max_errors 列为该引物的最大错配数
+fn read_csv(lines: &[String]) -> Result<Vec<PrimerRecord>> {
+    let mut rows = lines.iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = rows.next().ok_or_else(|| anyhow::anyhow!("CSV 文件为空"))?;
+    let header: Vec<String> = split_csv_line(header.trim_start_matches('\u{feff}'))
+        .iter()
+        .map(|h| h.to_lowercase())
+        .collect();
+    let find_column = |exact: &[&str], partial: &[&str], exclude: Option<usize>| {
+        let columns = || (0..header.len()).filter(|&i| Some(i) != exclude);
+        exact.iter()
+            .find_map(|key| columns().find(|&i| header[i] == *key))
+            .or_else(|| partial.iter().find_map(|key| columns().find(|&i| header[i].contains(key))))
+    };
+    let name_col = find_column(
+        &["name", "primer name", "primer_name", "oligo name", "primer", "oligo", "id"],
+        &["name", "primer", "oligo", "id"],
+        None,
+    )
+        .ok_or_else(|| anyhow::anyhow!("CSV 表头中没有找到引物名称列: {}", header.join(",")))?;
+    let seq_col = find_column(&["sequence", "seq", "sequence (5'-3')"], &["seq"], Some(name_col))
+        .ok_or_else(|| anyhow::anyhow!("CSV 表头中没有找到序列列: {}", header.join(",")))?;
+    let errors_col = header.iter().position(|h| h == "max_errors");
+
+    let mut records = Vec::new();
+    for (line_no, line) in rows {
+        let fields = split_csv_line(line);
+        let (name, seq) = match (fields.get(name_col), fields.get(seq_col)) {
+            (Some(name), Some(seq)) if !name.is_empty() => (name, seq),
+            _ => {
+                eprintln!("警告: 跳过格式不正确的行: {}", line);
+                continue;
+            },
+        };
+        let max_errors = match errors_col.and_then(|c| fields.get(c)).map(|s| s.as_str()) {
+            Some(s) if !s.is_empty() && s != "-" => Some(s.parse::<i32>()
+                .ok()
+                .filter(|k| *k >= 0)
+                .ok_or_else(|| anyhow::anyhow!("引物文件第 {} 行最大错配数无效: {}", line_no + 1, s))?),
+            _ => None,
+        };
+        records.push(PrimerRecord {
+            line_no: line_no + 1,
+            name: name.clone(),
+            seq: seq.split_whitespace().collect(),
+            max_errors,
+        });
+    }
+    Ok(records)
+}
+
+// 按逗号拆分一行 CSV，支持双引号包围的字段和 "" 转义
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+// ARTIC 引物方案：chrom start end name pool [strand [sequence]]，坐标为 0-based 半开区间。
+// 名称形如 {扩增子}_LEFT 或 {扩增子}_RIGHT（可带 _alt1 等后缀），RIGHT 引物的序列为参考序列的反向互补。
+// 没有序列列时从参考基因组中提取
+fn read_bed(lines: &[String], reference: Option<&str>) -> Result<(Vec<PrimerRecord>, Vec<SchemeAmplicon>)> {
+    let reference = match reference {
+        Some(path) => Some(read_reference(path).context("加载参考基因组失败")?),
+        None => None,
+    };
+
+    // 扩增子名称 -> (LEFT 引物, RIGHT 引物)，每个引物为 (名称, 起始, 终止, 引物池)
+    type BedPrimer = (String, usize, usize, String);
+    let mut order: Vec<String> = Vec::new();
+    let mut schemes: HashMap<String, (Vec<BedPrimer>, Vec<BedPrimer>)> = HashMap::new();
+    let mut records = Vec::new();
+
+    for (line_no, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').map(|p| p.trim()).collect();
+        if parts.len() < 5 {
+            anyhow::bail!("BED 文件第 {} 行格式不正确，至少需要 chrom/start/end/name/pool 五列: {}", line_no + 1, line);
+        }
+
+        let chrom = parts[0].trim_start_matches('\u{feff}');
+        let invalid = || anyhow::anyhow!("BED 文件第 {} 行坐标无效: {}", line_no + 1, line);
+        let start = parts[1].parse::<usize>().map_err(|_| invalid())?;
+        let end = parts[2].parse::<usize>().map_err(|_| invalid())?;
+        if start >= end {
+            return Err(invalid());
+        }
+        let name = parts[3];
+        let (amplicon, is_left) = match (name.rfind("_LEFT"), name.rfind("_RIGHT")) {
+            (Some(i), None) => (&name[..i], true),
+            (None, Some(i)) => (&name[..i], false),
+            _ => anyhow::bail!("BED 文件第 {} 行引物名称缺少 _LEFT 或 _RIGHT 后缀: {}", line_no + 1, name),
+        };
+
+        let seq = match (parts.get(6).filter(|s| !s.is_empty()), &reference) {
+            (Some(seq), _) => seq.to_uppercase(),
+            (None, Some(reference)) => {
+                let chrom_seq = reference.get(chrom).ok_or_else(|| {
+                    anyhow::anyhow!("BED 文件第 {} 行的序列 {} 不在参考基因组中", line_no + 1, chrom)
+                })?;
+                let region = chrom_seq.get(start..end).ok_or_else(|| {
+                    anyhow::anyhow!("BED 文件第 {} 行坐标超出参考序列 {} 的长度 {}", line_no + 1, chrom, chrom_seq.len())
+                })?;
+                let region = if is_left { region.to_vec() } else { revcomp(region) };
+                String::from_utf8_lossy(&region).to_uppercase()
+            },
+            (None, None) => anyhow::bail!(
+                "BED 文件第 {} 行没有序列列，需要通过 --reference 提供参考基因组", line_no + 1
+            ),
+        };
+
+        records.push(PrimerRecord {
+            line_no: line_no + 1,
+            name: name.to_string(),
+            seq,
+            max_errors: None,
+        });
+        if !schemes.contains_key(amplicon) {
+            order.push(amplicon.to_string());
+        }
+        let (lefts, rights) = schemes.entry(amplicon.to_string()).or_default();
+        let primer = (name.to_string(), start, end, parts[4].to_string());
+        if is_left { lefts.push(primer) } else { rights.push(primer) }
+    }
+
+    // 每个扩增子的 LEFT 与 RIGHT 引物两两组合。替补引物（如 _LEFT_alt1）与主引物属于同一个扩增子，
+    // 读段按扩增子名称计数，不单独作为扩增子；名称以 _LEFT/_RIGHT 结尾的引物为主引物，没有时取文件中的第一个
+    let mut amplicons = Vec::new();
+    for name in order {
+        let (lefts, rights) = schemes.get_mut(&name).unwrap();
+        if lefts.is_empty() || rights.is_empty() {
+            eprintln!("警告: 引物方案中的扩增子 {} 缺少 {} 引物，已跳过", name, if lefts.is_empty() { "LEFT" } else { "RIGHT" });
+            continue;
+        }
+        lefts.sort_by_key(|l| !l.0.ends_with("_LEFT"));
+        rights.sort_by_key(|r| !r.0.ends_with("_RIGHT"));
+
+        let mut combinations = Vec::new();
+        for left in lefts.iter() {
+            for right in rights.iter() {
+                if right.2 <= left.1 {
+                    anyhow::bail!("引物方案中 {} 与 {} 的坐标无法组成扩增子", left.0, right.0);
+                }
+                combinations.push((left.0.clone(), right.0.clone(), right.2 - left.1));
+            }
+        }
+        let sizes = combinations.iter().map(|c| c.2);
+        let size = (sizes.clone().min().unwrap_or(0), sizes.max().unwrap_or(0));
+        let mut combinations = combinations.into_iter().map(|(left, right, _)| (left, right));
+        let (forward, reverse) = combinations.next().unwrap();
+        amplicons.push(SchemeAmplicon {
+            name,
+            forward,
+            reverse,
+            alternatives: combinations.collect(),
+            size,
+            pool: Some(lefts[0].3.clone()),
+        });
+    }
+
+    Ok((records, amplicons))
+}
+
+// 参考基因组：序列名称（> 之后的第一个词）-> 大写序列
+fn read_reference(path: &str) -> Result<HashMap<String, Vec<u8>>> {
+    let mut reference: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in read_lines(path)? {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('>') {
+            let name = header.split_whitespace().next().unwrap_or("").to_string();
+            reference.insert(name.clone(), Vec::new());
+            current = Some(name);
+        } else if let Some(seq) = current.as_ref().and_then(|name| reference.get_mut(name)) {
+            seq.extend(line.bytes().map(|b| b.to_ascii_uppercase()));
+        }
+    }
+    if reference.is_empty() {
+        anyhow::bail!("参考基因组文件中没有序列: {}", path);
+    }
+    Ok(reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|l| l.to_string()).collect()
+    }
+
+    fn names_and_seqs(records: &[PrimerRecord]) -> Vec<(&str, &str)> {
+        records.iter().map(|r| (r.name.as_str(), r.seq.as_str())).collect()
+    }
+
+    #[test]
+    fn detect_format_ignores_gz_suffix() {
+        assert_eq!(detect_format("scheme.primer.bed.gz").unwrap(), PrimerFormat::Bed);
+        assert_eq!(detect_format("primers.FASTA.gz").unwrap(), PrimerFormat::Fasta);
+        assert_eq!(detect_format("primers.csv").unwrap(), PrimerFormat::Csv);
+    }
+
+    #[test]
+    fn csv_simple_header() {
+        let records = read_csv(&lines("name,sequence\nP1,ACGTACGT\nP2,TTGGCCAA\n")).unwrap();
+        assert_eq!(names_and_seqs(&records), [("P1", "ACGTACGT"), ("P2", "TTGGCCAA")]);
+        assert_eq!(records[1].line_no, 3);
+    }
+
+    #[test]
+    fn csv_sequence_name_header() {
+        let records = read_csv(&lines("Sequence Name,Sequence\nP1,ACGTACGT\n")).unwrap();
+        assert_eq!(names_and_seqs(&records), [("P1", "ACGTACGT")]);
+    }
+
+    #[test]
+    fn csv_portal_export() {
+        let text = "\u{feff}Oligo Name,Sequence (5'-3'),Scale,max_errors\n\
+                    \"P1, forward\",\"ACGT ACGT\",25nm,1\n\
+                    \"P2 \"\"rev\"\"\",TTGGCCAA,25nm,-\n";
+        let records = read_csv(&lines(text)).unwrap();
+        assert_eq!(names_and_seqs(&records), [("P1, forward", "ACGTACGT"), ("P2 \"rev\"", "TTGGCCAA")]);
+        assert_eq!(records[0].max_errors, Some(1));
+        assert_eq!(records[1].max_errors, None);
+    }
+
+    #[test]
+    fn csv_missing_sequence_column() {
+        assert!(read_csv(&lines("name,scale\nP1,25nm\n")).is_err());
+    }
+
+    #[test]
+    fn split_csv_quotes() {
+        assert_eq!(split_csv_line(r#"a, "b,c" ,"d""e","#), ["a", "b,c", "d\"e", ""]);
+    }
+
+    #[test]
+    fn bed_scheme_with_alt_primers() {
+        let text = "MN1\t30\t40\tnCoV_1_LEFT_alt1\tnCoV_1\t+\tCCCCCGGGGG\n\
+                    MN1\t20\t30\tnCoV_1_LEFT\tnCoV_1\t+\tACGTACGTAA\n\
+                    MN1\t190\t200\tnCoV_1_RIGHT\tnCoV_1\t-\tTTTTTAAAAA\n\
+                    MN1\t300\t310\tnCoV_2_LEFT\tnCoV_2\t+\tGGGGGTTTTT\n\
+                    MN1\t400\t410\tnCoV_2_RIGHT\tnCoV_2\t-\tAAAAACCCCC\n";
+        let (records, amplicons) = read_bed(&lines(text), None).unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(amplicons.len(), 2);
+
+        let amp = &amplicons[0];
+        assert_eq!(amp.name, "nCoV_1");
+        assert_eq!((amp.forward.as_str(), amp.reverse.as_str()), ("nCoV_1_LEFT", "nCoV_1_RIGHT"));
+        assert_eq!(amp.alternatives, [("nCoV_1_LEFT_alt1".to_string(), "nCoV_1_RIGHT".to_string())]);
+        assert_eq!(amp.size, (170, 180));
+        assert_eq!(amp.pool.as_deref(), Some("nCoV_1"));
+
+        assert_eq!(amplicons[1].name, "nCoV_2");
+        assert!(amplicons[1].alternatives.is_empty());
+        assert_eq!(amplicons[1].size, (110, 110));
+    }
+
+    #[test]
+    fn bed_without_sequence_needs_reference() {
+        let text = "MN1\t20\t30\tnCoV_1_LEFT\t1\t+\n";
+        assert!(read_bed(&lines(text), None).is_err());
+    }
+}
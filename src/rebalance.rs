@@ -0,0 +1,226 @@
+// rebalance 子命令：根据一个或多个样本的 {sample}_statistics.json 中的引物对计数（PrimerPairStat），
+// 计算每个扩增子的相对扩增效率，给出下一轮引物浓度的调整倍数
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct RebalanceArgs {
+    /// 一个或多个 {sample}_statistics.json 文件
+    #[arg(short, long, num_args = 1.., required = true)]
+    statistics: Vec<String>,
+
+    /// 输出目录
+    #[arg(short = 'O', long, default_value = "output")]
+    outdir: String,
+
+    /// 调整倍数的下限
+    #[arg(long, default_value = "0.25")]
+    min_multiplier: f64,
+
+    /// 调整倍数的上限
+    #[arg(long, default_value = "4.0")]
+    max_multiplier: f64,
+}
+
+// statistics JSON 中 rebalance 需要的字段
+#[derive(Deserialize)]
+struct SampleStatistics {
+    sample_name: String,
+    primer_pairs: Vec<PairRecord>,
+}
+
+#[derive(Deserialize)]
+struct PairRecord {
+    amplicon: Option<String>,
+    forward_primer: String,
+    reverse_primer: String,
+    pool: Option<String>,
+    unexpected: bool,
+    assigned: f64,
+}
+
+// 单个扩增子在所有样本中的相对效率（读段数 / 该样本扩增子平均读段数）
+#[derive(Default)]
+struct PairEfficiency {
+    pool: Option<String>,
+    reads: Vec<f64>,
+    efficiencies: Vec<f64>,
+}
+
+// 有面板时只使用声明的扩增子，否则使用观察到的引物对（跳过非预期引物对和未找到引物对的读段）
+fn usable_pairs(pairs: &[PairRecord]) -> Vec<&PairRecord> {
+    let has_panel = pairs.iter().any(|p| p.amplicon.is_some());
+    pairs
+        .iter()
+        .filter(|p| if has_panel { p.amplicon.is_some() } else { !p.unexpected && p.forward_primer != "-" })
+        .collect()
+}
+
+// 调整倍数为效率的倒数并截断到 [min, max]，返回 (调整倍数, 是否被截断)；效率为 0 时取上限
+fn multiplier(efficiency: f64, min: f64, max: f64) -> (f64, bool) {
+    let multiplier = if efficiency > 0.0 { 1.0 / efficiency } else { f64::INFINITY };
+    let clamped = multiplier.clamp(min, max);
+    (clamped, clamped != multiplier)
+}
+
+pub fn run(args: RebalanceArgs) -> Result<()> {
+    if args.min_multiplier <= 0.0 || args.min_multiplier > args.max_multiplier {
+        anyhow::bail!("调整倍数范围无效: {} - {}", args.min_multiplier, args.max_multiplier);
+    }
+    std::fs::create_dir_all(&args.outdir)?;
+
+    // 扩增子名称（非面板引物对为 正向-反向）-> 效率
+    let mut pairs: BTreeMap<(String, String, String), PairEfficiency> = BTreeMap::new();
+    for path in &args.statistics {
+        let file = File::open(path).with_context(|| format!("无法打开统计结果文件: {}", path))?;
+        let stats: SampleStatistics = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("无法解析统计结果文件: {}", path))?;
+
+        let amplicons = usable_pairs(&stats.primer_pairs);
+        if amplicons.is_empty() {
+            eprintln!("警告: 样本 {} 没有可用的引物对，已跳过", stats.sample_name);
+            continue;
+        }
+
+        let mean = amplicons.iter().map(|p| p.assigned).sum::<f64>() / amplicons.len() as f64;
+        if mean <= 0.0 {
+            eprintln!("警告: 样本 {} 的扩增子没有读段，已跳过", stats.sample_name);
+            continue;
+        }
+        println!("样本 {}: {} 个扩增子，平均读数 {:.2}", stats.sample_name, amplicons.len(), mean);
+
+        for pair in amplicons {
+            let name = pair.amplicon.clone()
+                .unwrap_or_else(|| format!("{}-{}", pair.forward_primer, pair.reverse_primer));
+            let entry = pairs
+                .entry((name, pair.forward_primer.clone(), pair.reverse_primer.clone()))
+                .or_default();
+            if entry.pool.is_none() {
+                entry.pool = pair.pool.clone();
+            }
+            entry.reads.push(pair.assigned);
+            entry.efficiencies.push(pair.assigned / mean);
+        }
+    }
+
+    if pairs.is_empty() {
+        anyhow::bail!("统计结果文件中没有可用的引物对");
+    }
+
+    let clamp = |efficiency: f64| multiplier(efficiency, args.min_multiplier, args.max_multiplier);
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+
+    let pairs_path = PathBuf::from(&args.outdir).join("rebalance_pairs.tsv");
+    let mut writer = std::io::BufWriter::new(
+        File::create(&pairs_path).with_context(|| format!("无法创建输出文件: {}", pairs_path.display()))?,
+    );
+    writeln!(writer, "Amplicon\tForward_Primer\tReverse_Primer\tPool\tSamples\tMean_Reads\tEfficiency\tMultiplier\tClamped")?;
+
+    // 引物池 -> 池内扩增子效率
+    let mut pools: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for ((name, forward, reverse), pair) in &pairs {
+        let efficiency = mean(&pair.efficiencies);
+        let (multiplier, clamped) = clamp(efficiency);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{:.2}\t{:.3}\t{:.3}\t{}",
+            name,
+            forward,
+            reverse,
+            pair.pool.as_deref().unwrap_or("-"),
+            pair.efficiencies.len(),
+            mean(&pair.reads),
+            efficiency,
+            multiplier,
+            clamped,
+        )?;
+        if let Some(pool) = &pair.pool {
+            pools.entry(pool.clone()).or_default().push(efficiency);
+        }
+    }
+    writer.flush()?;
+    println!("引物对调整倍数已写入: {}", pairs_path.display());
+
+    if !pools.is_empty() {
+        let pools_path = PathBuf::from(&args.outdir).join("rebalance_pools.tsv");
+        let mut writer = std::io::BufWriter::new(
+            File::create(&pools_path).with_context(|| format!("无法创建输出文件: {}", pools_path.display()))?,
+        );
+        writeln!(writer, "Pool\tAmplicons\tEfficiency\tMultiplier\tClamped")?;
+        println!("\n引物池调整倍数:");
+        for (pool, efficiencies) in &pools {
+            let efficiency = mean(efficiencies);
+            let (multiplier, clamped) = clamp(efficiency);
+            writeln!(writer, "{}\t{}\t{:.3}\t{:.3}\t{}", pool, efficiencies.len(), efficiency, multiplier, clamped)?;
+            println!("{}: 效率 {:.3}，调整倍数 {:.3}{}", pool, efficiency, multiplier, if clamped { "（已截断）" } else { "" });
+        }
+        writer.flush()?;
+        println!("引物池调整倍数已写入: {}", pools_path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(amplicon: Option<&str>, forward: &str, reverse: &str, unexpected: bool) -> PairRecord {
+        PairRecord {
+            amplicon: amplicon.map(str::to_string),
+            forward_primer: forward.to_string(),
+            reverse_primer: reverse.to_string(),
+            pool: None,
+            unexpected,
+            assigned: 10.0,
+        }
+    }
+
+    fn names(pairs: &[&PairRecord]) -> Vec<String> {
+        pairs.iter().map(|p| format!("{}-{}", p.forward_primer, p.reverse_primer)).collect()
+    }
+
+    #[test]
+    fn multiplier_is_inverse_efficiency_within_range() {
+        assert_eq!(multiplier(0.5, 0.25, 4.0), (2.0, false));
+        assert_eq!(multiplier(2.0, 0.25, 4.0), (0.5, false));
+        // 恰好落在边界上不算截断
+        assert_eq!(multiplier(0.25, 0.25, 4.0), (4.0, false));
+        assert_eq!(multiplier(4.0, 0.25, 4.0), (0.25, false));
+    }
+
+    #[test]
+    fn multiplier_clamps_to_range() {
+        assert_eq!(multiplier(0.1, 0.25, 4.0), (4.0, true));
+        assert_eq!(multiplier(10.0, 0.25, 4.0), (0.25, true));
+        // 效率为 0 时倍数为无穷大，截断到上限
+        assert_eq!(multiplier(0.0, 0.25, 4.0), (4.0, true));
+    }
+
+    #[test]
+    fn usable_pairs_prefers_panel_amplicons() {
+        let pairs = vec![
+            record(Some("A1"), "F1", "R1", false),
+            record(None, "F1", "R2", true),
+            record(None, "F3", "R3", false),
+            record(None, "-", "-", false),
+        ];
+        assert_eq!(names(&usable_pairs(&pairs)), vec!["F1-R1"]);
+    }
+
+    #[test]
+    fn usable_pairs_without_panel_uses_observed_pairs() {
+        let pairs = vec![
+            record(None, "F1", "R1", false),
+            record(None, "F1", "R2", true),
+            record(None, "F3", "R3", false),
+            record(None, "-", "-", false),
+        ];
+        assert_eq!(names(&usable_pairs(&pairs)), vec!["F1-R1", "F3-R3"]);
+    }
+}
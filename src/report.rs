@@ -0,0 +1,224 @@
+// HTML 报告：使用 templates/report.html 模板生成自包含（内联样式、无外部资源）的报告
+use crate::{format_count, FastqRecord, PrimerMatch, PrimerSet, ReadAnalysis, StatisticsOutput};
+use edlib_rs::edlibrs::{EDLIB_EDOP_DELETE, EDLIB_EDOP_INSERT, EDLIB_EDOP_MATCH};
+use std::collections::HashMap;
+
+const TEMPLATE: &str = include_str!("../templates/report.html");
+
+type Row = HashMap<&'static str, String>;
+
+pub fn render_report(
+    stats: &StatisticsOutput,
+    previews: &[(ReadAnalysis, FastqRecord)],
+    primer_set: &PrimerSet,
+) -> String {
+    let mut vars = Row::new();
+    vars.insert("sample_name", stats.sample_name.clone());
+    vars.insert("preview_count", previews.len().to_string());
+    vars.insert("timestamp", chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string());
+
+    let mut lists: HashMap<&str, Vec<Row>> = HashMap::new();
+    lists.insert("stats", stat_rows(stats));
+    lists.insert("categories", stats.categories
+        .iter()
+        .map(|c| row(&[
+            ("category", c.category.to_string()),
+            ("count", c.count.to_string()),
+            ("percentage", format!("{:.2}%", c.percentage)),
+        ]))
+        .collect());
+    lists.insert("primer_pairs", stats.primer_pairs
+        .iter()
+        .map(|p| row(&[
+            ("amplicon", match (&p.amplicon, p.unexpected) {
+                (Some(name), _) => name.clone(),
+                (None, true) => "unexpected".to_string(),
+                (None, false) => "-".to_string(),
+            }),
+            ("forward_primer", p.forward_primer.clone()),
+            ("reverse_primer", p.reverse_primer.clone()),
+            ("count", format_count(p.assigned)),
+            ("ambiguous", p.ambiguous.to_string()),
+            ("size_classes", p.size_classes.as_ref().map_or("-".to_string(), |classes| {
+                classes.iter().map(|(c, n)| format!("{} {}", c, n)).collect::<Vec<_>>().join(", ")
+            })),
+            ("percentage", format!("{:.2}%", p.percentage)),
+        ]))
+        .collect());
+    lists.insert("analyses", previews
+        .iter()
+        .map(|(analysis, record)| analysis_row(analysis, record, primer_set))
+        .collect());
+
+    render_template(TEMPLATE, &vars, &lists)
+}
+
+fn row(fields: &[(&'static str, String)]) -> Row {
+    fields.iter().cloned().collect()
+}
+
+fn stat_rows(stats: &StatisticsOutput) -> Vec<Row> {
+    let mut rows = vec![
+        ("Sample", stats.sample_name.clone()),
+        ("Total Reads", stats.total_reads.to_string()),
+        ("Both Primers Found", format!("{} ({:.2}%)", stats.both_primers_found, stats.success_rate)),
+        ("Plus Strand", stats.plus_strand.to_string()),
+        ("Minus Strand", stats.minus_strand.to_string()),
+        ("Primer Dimers", format!("{} ({:.2}%)", stats.dimer_count, stats.dimer_rate)),
+        ("Unexpected Pairs", format!("{} ({:.2}%)", stats.unexpected_pair_count, stats.unexpected_pair_rate)),
+        ("Internal Priming", format!("{} ({:.2}%)", stats.internal_priming_count, stats.internal_priming_rate)),
+        ("Single Primer", format!("{} ({:.2}%)", stats.single_primer_count, stats.single_primer_rate)),
+        ("Ambiguous Pairs", format!("{} ({:.2}%, {})", stats.ambiguous_count, stats.ambiguous_rate, stats.ambiguous_policy)),
+    ];
+    if let Some(trimming) = &stats.trimming {
+        rows.push(("Trimmed Reads", trimming.trimmed_reads.to_string()));
+    }
+    if let Some(uniformity) = &stats.uniformity {
+        rows.push(("Amplicon Mean / Median Reads", format!("{:.2} / {:.2}", uniformity.mean_reads, uniformity.median_reads)));
+        rows.push(("Coverage CV", format!("{:.3}", uniformity.cv)));
+        rows.push(("Fold-80 Penalty", uniformity.fold_80_penalty.map_or("-".to_string(), |f| format!("{:.2}", f))));
+        rows.push(("Dropout Amplicons", if uniformity.dropouts.is_empty() {
+            "-".to_string()
+        } else {
+            uniformity.dropouts.join(", ")
+        }));
+    }
+
+    rows.into_iter()
+        .map(|(name, value)| row(&[("name", name.to_string()), ("value", value)]))
+        .collect()
+}
+
+fn analysis_row(analysis: &ReadAnalysis, record: &FastqRecord, primer_set: &PrimerSet) -> Row {
+    // F 侧是引物正向序列的命中，R 侧是引物反向互补序列的命中
+    let alignment = |name: &str, m: &PrimerMatch, reverse: bool| {
+        let primer = primer_set.primers.iter().find(|p| p.name == name);
+        match (primer, m.position, m.end, &m.alignment) {
+            (Some(primer), Some(start), Some(end), Some(ops)) => {
+                let query = if reverse { &primer.rc_seq } else { &primer.seq };
+                pretty_alignment(query.as_bytes(), &record.seq[start..end], ops)
+            },
+            _ => (String::new(), String::new(), String::new()),
+        }
+    };
+    let (f_query, f_match, f_target) = alignment(&analysis.f_primer, &analysis.f_match, false);
+    let (r_query, r_match, r_target) = alignment(&analysis.r_primer, &analysis.r_match, true);
+
+    row(&[
+        ("read_id", analysis.read_id.clone()),
+        ("length", analysis.length.to_string()),
+        ("strand", analysis.strand.to_string()),
+        ("category", analysis.category.as_str().to_string()),
+        ("f_primer", format!("{}  {}", analysis.f_primer, analysis.f_match.cigar())),
+        ("f_query", f_query),
+        ("f_match", f_match),
+        ("f_target", f_target),
+        ("r_primer", format!("{}  {}", analysis.r_primer, analysis.r_match.cigar())),
+        ("r_query", r_query),
+        ("r_match", r_match),
+        ("r_target", r_target),
+        ("distance", analysis.distance.map_or("-".to_string(), |d| d.to_string())),
+        ("insert_length", analysis.insert_length.map_or("-".to_string(), |l| l.to_string())),
+        ("overlap", analysis.overlap.map_or("-".to_string(), |o| o.to_string())),
+        ("is_dimer", analysis.is_dimer.to_string()),
+    ])
+}
+
+// 根据 edlib 比对路径生成三行比对：引物、匹配标记（| 匹配，. 错配，空格为插入/缺失）、读段
+fn pretty_alignment(query: &[u8], target: &[u8], ops: &[u8]) -> (String, String, String) {
+    let mut q_line = String::with_capacity(ops.len());
+    let mut m_line = String::with_capacity(ops.len());
+    let mut t_line = String::with_capacity(ops.len());
+    let (mut qi, mut ti) = (0, 0);
+
+    for &op in ops {
+        let q = query.get(qi).map_or('-', |&b| b as char);
+        let t = target.get(ti).map_or('-', |&b| b as char);
+        match op {
+            EDLIB_EDOP_INSERT => {
+                q_line.push(q);
+                m_line.push(' ');
+                t_line.push('-');
+                qi += 1;
+            },
+            EDLIB_EDOP_DELETE => {
+                q_line.push('-');
+                m_line.push(' ');
+                t_line.push(t);
+                ti += 1;
+            },
+            _ => {
+                q_line.push(q);
+                m_line.push(if op == EDLIB_EDOP_MATCH { '|' } else { '.' });
+                t_line.push(t);
+                qi += 1;
+                ti += 1;
+            },
+        }
+    }
+
+    (q_line, m_line, t_line)
+}
+
+// 简单的模板渲染：{{#each name}}...{{/each}} 对列表中的每一行重复块内容，{{key}} 替换为变量值。
+// 所有值都会做 HTML 转义
+fn render_template(template: &str, vars: &Row, lists: &HashMap<&str, Vec<Row>>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{#each ") {
+        output.push_str(&replace_vars(&rest[..start], vars));
+        let after = &rest[start + "{{#each ".len()..];
+        let name_end = match after.find("}}") {
+            Some(i) => i,
+            None => break,
+        };
+        let name = after[..name_end].trim();
+        let body_start = &after[name_end + 2..];
+        let body_end = body_start.find("{{/each}}").unwrap_or(body_start.len());
+        let body = &body_start[..body_end];
+
+        for row in lists.get(name).map(|rows| rows.as_slice()).unwrap_or(&[]) {
+            output.push_str(&replace_vars(body, row));
+        }
+        rest = body_start.get(body_end + "{{/each}}".len()..).unwrap_or("");
+    }
+    output.push_str(&replace_vars(rest, vars));
+    output
+}
+
+fn replace_vars(text: &str, vars: &Row) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        match rest[start..].find("}}") {
+            Some(len) => {
+                let key = rest[start + 2..start + len].trim();
+                output.push_str(&escape_html(vars.get(key).map(|v| v.as_str()).unwrap_or("")));
+                rest = &rest[start + len + 2..];
+            },
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            },
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}